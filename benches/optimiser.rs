@@ -0,0 +1,37 @@
+// Benchmarks of the optimiser on the ode fitting problem, and of the rk2 solver it spends its time
+// in. Run with cargo bench
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use implicit_filtering::euler::rk2;
+use implicit_filtering::testfuncs::exponential_mse;
+use implicit_filtering::{implicit_filtering_with, FilterConfig};
+
+fn bench_optimiser(c: &mut Criterion){
+    // with the default min_h the final stencil sizes call for hundreds of millions of rk2 steps, 
+    // so the schedule is cut off where a single run takes milliseconds rather than seconds
+    let config = FilterConfig{verbose: false, min_h: Some(1e-4), ..FilterConfig::default()};
+
+    // the starting point, stencil size and tolerance are passed through black_box so that the 
+    // whole run can't be evaluated at compile time
+    c.bench_function("implicit_filtering exponential_mse", |b| b.iter(|| {
+        implicit_filtering_with(exponential_mse, black_box(1.5), black_box(0.1), black_box(1e-7), &config)
+    }));
+}
+
+fn bench_rk2(c: &mut Criterion){
+    let mut group = c.benchmark_group("rk2");
+
+    for &stepsize in &[0.1, 0.01, 0.001]{
+        group.bench_with_input(BenchmarkId::from_parameter(stepsize), &stepsize, |b, &stepsize| {
+            b.iter(|| rk2(black_box(1.0), stepsize, black_box(5.0)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_optimiser, bench_rk2);
+criterion_main!(benches);
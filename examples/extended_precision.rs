@@ -0,0 +1,29 @@
+// Fitting a parameter to more digits than f64 can hold, by running the optimiser on TwoFloat, a 
+// double-double type with about 32 significant decimal digits. Run with
+//
+//     cargo run --example extended_precision --features twofloat
+
+use implicit_filtering::{implicit_filtering_with, FilterConfig, TwoFloat};
+
+fn main(){
+    // the minimum is at 1/3, which needs the low word of a TwoFloat to be represented closely.
+    // Dividing by an f64 keeps that low word, where twofloat's TwoFloat by TwoFloat division doesn't
+    let third = TwoFloat::from(1.0)/3.0;
+
+    let mse = |x: TwoFloat, _h: TwoFloat| (x - third)*(x - third);
+
+    let config = FilterConfig{verbose: false, ..FilterConfig::default()};
+
+    let result = implicit_filtering_with(mse, TwoFloat::from(2.0), TwoFloat::from(0.5), TwoFloat::from(1e-30), &config);
+
+    match result{
+        Ok(result) => {
+            let error = result.x - third;
+
+            println!("x - 1/3 = {:e} in extended precision", f64::from(error));
+            // the closest an f64 can come to 1/3
+            println!("x - 1/3 = {:e} at best in f64", f64::from(TwoFloat::from(1.0/3.0) - third));
+        },
+        Err(err)   => println!("Optimisation failed: {}", err),
+    }
+}
@@ -0,0 +1,15 @@
+
+// A no_std crate calling the scalar optimiser, so that building it for a target without the 
+// standard library shows that nothing in that path needs std
+
+#![no_std]
+
+use implicit_filtering::{implicit_filtering, OptimResult};
+
+fn mse(x: f64, _h: f64) -> f64{
+    (x - 1.0)*(x - 1.0)
+}
+
+pub fn minimise() -> Option<OptimResult>{
+    implicit_filtering(mse, 1.5, 0.1, 1e-7).ok()
+}
@@ -0,0 +1,165 @@
+
+// A fluent interface to implicit_filtering_with, e.g.
+//
+//     ImplicitFilter::new(mse).x0(1.5).h0(0.1).tol(1e-7).run()
+//
+// x0 must be set. If h0 isn't, it is chosen by estimate_h0; everything else defaults to the values
+// used by implicit_filtering
+
+use num_traits::Float;
+
+use crate::{FilterConfig, Objective, OptimError, OptimResult, StencilSchedule};
+
+const TOLERANCE: f64 = 1e-7;
+
+pub struct ImplicitFilter<F, T = f64>{
+    mse    : F,
+    x0     : Option<T>,
+    h0     : Option<T>,
+    tol    : T,
+    config : FilterConfig,
+}
+
+impl<F, T> ImplicitFilter<F, T>
+    where T: Float, F: Objective<T>
+{
+    pub fn new(mse: F) -> ImplicitFilter<F, T>{
+        ImplicitFilter{mse,
+                       x0:     None,
+                       h0:     None,
+                       tol:    crate::cast(TOLERANCE),
+                       config: FilterConfig::default()}
+    }
+
+    // starting point
+    pub fn x0(mut self, x0: T) -> Self{
+        self.x0 = Some(x0);
+        self
+    }
+
+    // initial stencil size, or None to estimate one
+    pub fn h0<H: Into<Option<T>>>(mut self, h0: H) -> Self{
+        self.h0 = h0.into();
+        self
+    }
+
+    // stop once reducing the stencil size moves x by no more than this
+    pub fn tol(mut self, tol: T) -> Self{
+        self.tol = tol;
+        self
+    }
+
+    pub fn max_outer(mut self, max_outer_iters: usize) -> Self{
+        self.config.max_outer_iters = max_outer_iters;
+        self
+    }
+
+    // set both max_inner_iters and max_line_search_iters
+    pub fn max_iters(mut self, max_iters: usize) -> Self{
+        self.config.max_inner_iters       = max_iters;
+        self.config.max_line_search_iters = max_iters;
+        self
+    }
+
+    pub fn max_inner_iters(mut self, max_inner_iters: usize) -> Self{
+        self.config.max_inner_iters = max_inner_iters;
+        self
+    }
+
+    pub fn max_line_search_iters(mut self, max_line_search_iters: usize) -> Self{
+        self.config.max_line_search_iters = max_line_search_iters;
+        self
+    }
+
+    pub fn line_search_reduction(mut self, line_search_reduction: f64) -> Self{
+        self.config.line_search_reduction = line_search_reduction;
+        self
+    }
+
+    // use the geometric schedule h0*stencil_reduction^i
+    pub fn stencil_reduction(mut self, stencil_reduction: f64) -> Self{
+        self.config.schedule = StencilSchedule::Geometric{ratio: stencil_reduction};
+        self
+    }
+
+    pub fn schedule(mut self, schedule: StencilSchedule) -> Self{
+        self.config.schedule = schedule;
+        self
+    }
+
+    pub fn armijo_constant(mut self, armijo_constant: f64) -> Self{
+        self.config.armijo_constant = armijo_constant;
+        self
+    }
+
+    pub fn max_step(mut self, max_step: f64) -> Self{
+        self.config.max_step = max_step;
+        self
+    }
+
+    // log the diagnostic table
+    pub fn verbose(mut self, verbose: bool) -> Self{
+        self.config.verbose = verbose;
+        self
+    }
+
+    // replace every algorithm constant at once
+    pub fn config(mut self, config: FilterConfig) -> Self{
+        self.config = config;
+        self
+    }
+
+    pub fn run(mut self) -> Result<OptimResult<T>, OptimError>{
+        let x0 = self.x0.ok_or(OptimError::MissingParameter("x0"))?;
+        let h0 = match self.h0{
+                   Some(h0) => h0,
+                   None     => crate::estimate_h0(&mut self.mse, x0),
+                 };
+
+        crate::implicit_filtering_with(self.mse, x0, h0, self.tol, &self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn quad(x: f64, _h: f64) -> f64{
+        (x - 1.0).powi(2)
+    }
+
+    #[test]
+    fn starting_point_is_required(){
+        assert!(ImplicitFilter::new(quad).h0(0.1).run() == Err(OptimError::MissingParameter("x0")));
+    }
+
+    #[test]
+    fn fluent_run_matches_implicit_filtering(){
+        let result = ImplicitFilter::new(quad).x0(1.5).h0(0.1).tol(1e-7).run().unwrap();
+
+        assert!(result == crate::implicit_filtering(quad, 1.5, 0.1, 1e-7).unwrap());
+        assert!((result.x - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn config_is_passed_through(){
+        // a single inner iteration at a single stencil size stops well short of the minimum
+        let quartic = |x: f64, _h: f64| (x - 1.0).powi(4);
+        let config  = FilterConfig{max_outer_iters: 1, max_inner_iters: 1, max_line_search_iters: 1, 
+                                   ..FilterConfig::default()};
+        let short   = ImplicitFilter::new(quartic).x0(3.0).h0(0.5).config(config.clone()).run().unwrap();
+
+        assert!(short == crate::implicit_filtering_with(quartic, 3.0, 0.5, TOLERANCE, &config).unwrap());
+        assert!(short == ImplicitFilter::new(quartic).x0(3.0).h0(0.5).max_outer(1).max_iters(1).run().unwrap());
+        assert!((short.x - 1.0).abs() > 0.1);
+    }
+
+    #[test]
+    fn missing_h0_is_estimated(){
+        let h0        = crate::estimate_h0(&mut quad, 1.5);
+        let estimated = ImplicitFilter::new(quad).x0(1.5).tol(1e-7).run().unwrap();
+
+        assert!(estimated == crate::implicit_filtering(quad, 1.5, h0, 1e-7).unwrap());
+        assert!((estimated.x - 1.0).abs() < 1e-6);
+    }
+}
@@ -1,46 +1,232 @@
-// Solvers for the ode y'(t) = βy(t) with intial condition y(0) = y₀
+// Solvers for the ode y'(t) = f(t, y(t)) with intial condition y(0) = y₀. The plain entry points
+// solve the test problem y'(t) = βy(t)
+
+use crate::Objective;
 
 const Y0  : f64 = 1.0;
 const T0  : f64 = 0.0;
 
+// a remainder within this fraction of the stepsize is covered by a single final step, so that
+// rounding in the accumulated time doesn't leave a vanishingly short step at the end
+const STEP_SLACK : f64 = 1e-9;
 
-#[derive(Debug,Copy, Clone)]
-struct SolutionElement{
-    time : f64,
-    val  : f64,
+// the Newton iteration in each backward Euler step stops once the update is within this relative
+// tolerance, or after the maximum number of iterations
+const NEWTON_TOL       : f64   = 1e-12;
+const NEWTON_MAX_ITERS : usize = 50;
+
+// rk45 gives up after this many trial steps
+const RK45_MAX_STEPS : usize = 100_000;
+
+
+// the solution of the ode at a point in time
+#[derive(Debug,Copy, Clone, PartialEq)]
+pub struct SolutionElement{
+    pub time : f64,
+    pub val  : f64,
 }
 
-struct SolutionSequence{
-    stepsize  : f64,
-    beta      : f64,
-    soln_elem : SolutionElement,
+// the steps of the solution up to and including finish_time, each found from the last by stepper.
+// The initial condition itself isn't included. With a finish_time of f64::INFINITY the sequence 
+// never ends, so that it can be driven a step at a time by the caller
+pub struct SolutionSequence<F, S>{
+    stepsize    : f64,
+    finish_time : f64,
+    deriv       : F,
+    soln_elem   : SolutionElement,
+    stepper     : S,
 }
 
 const INITIAL_CONDITION: SolutionElement = SolutionElement{time: T0, val: Y0};
 
-impl SolutionSequence{
-   fn new(stepsize : f64, beta: f64) -> SolutionSequence{
+impl<F, S> SolutionSequence<F, S>
+    where S: FnMut(SolutionElement, &F, f64) -> SolutionElement
+{
+   /// The stepper takes the current element, the derivative and the signed step, and returns the
+   /// next element. It may be one of the steppers here, rk2_stepper() or rk4_next, or any other
+   /// method, and the sequence can be driven a step at a time:
+   ///
+   /// ```
+   /// use implicit_filtering::euler::{rk4_next, SolutionElement, SolutionSequence};
+   ///
+   /// let initial   = SolutionElement{time: 0.0, val: 1.0};
+   /// let euler     = |y: SolutionElement, deriv: &fn(f64, f64) -> f64, h: f64| 
+   ///                     SolutionElement{time: y.time + h, val: y.val + h*deriv(y.time, y.val)};
+   /// let mut steps = SolutionSequence::new(0.5, initial, 1.0, (|_, y| y) as fn(f64, f64) -> f64, euler);
+   ///
+   /// assert_eq!(steps.next(), Some(SolutionElement{time: 0.5, val: 1.5}));
+   /// assert_eq!(steps.next(), Some(SolutionElement{time: 1.0, val: 2.25}));
+   /// assert_eq!(steps.next(), None);
+   ///
+   /// let last = SolutionSequence::new(0.1, initial, 1.0, |_, y| y, rk4_next).last().unwrap();
+   /// assert!((last.val - 1f64.exp()).abs() < 1e-5);
+   /// ```
+   pub fn new(stepsize : f64, initial: SolutionElement, finish_time: f64, deriv: F, stepper: S) -> SolutionSequence<F, S>{
        SolutionSequence{stepsize, 
-                        beta, 
-                        soln_elem: INITIAL_CONDITION}
+                        finish_time,
+                        deriv, 
+                        soln_elem: initial,
+                        stepper}
    }
 }
 
-fn rk2_next(current: SolutionElement, beta: f64, stepsize: f64) -> SolutionElement{
+// The coefficients of an explicit Runge-Kutta method. Stage i is evaluated at t + c[i]*h using 
+// the weights a[i] on the earlier stages, and the stages are combined with the weights b
+#[derive(Debug, Clone, PartialEq)]
+pub struct ButcherTableau{
+    a : Vec<Vec<f64>>,
+    b : Vec<f64>,
+    c : Vec<f64>,
+}
+
+// The ways in which a Butcher tableau can be malformed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TableauError{
+    // the number of coefficients doesn't match the number of stages
+    Dimensions(&'static str),
+    // a stage depends on itself or a later stage, so the method isn't explicit
+    Implicit,
+}
+
+impl std::fmt::Display for TableauError{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result{
+        match self{
+            TableauError::Dimensions(msg) => write!(f, "inconsistent tableau: {}", msg),
+            TableauError::Implicit        => write!(f, "the tableau does not describe an explicit method"),
+        }
+    }
+}
+
+impl std::error::Error for TableauError{}
+
+// The ways in which rk2_checked and rk45 can fail
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OdeError{
+    // the stepsize is zero, negative or not finite
+    InvalidStepsize,
+    // the finish time is not finite, or for rk45 is before the initial time
+    InvalidFinishTime,
+    // the error tolerance of rk45 is zero, negative or not a number
+    InvalidTolerance,
+    // rk45 took its maximum number of trial steps, getting only as far as time
+    StepLimitReached{time: f64},
+}
+
+impl std::fmt::Display for OdeError{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result{
+        match self{
+            OdeError::InvalidStepsize        => write!(f, "the stepsize must be positive and finite"),
+            OdeError::InvalidFinishTime      => write!(f, "the finish time must be finite, and for rk45 no earlier than the initial time"),
+            OdeError::InvalidTolerance       => write!(f, "the tolerance must be positive"),
+            OdeError::StepLimitReached{time} => write!(f, "the step limit was reached at t = {}, before the finish time", time),
+        }
+    }
+}
+
+impl std::error::Error for OdeError{}
 
-    let deriv = |y| beta*y;
+impl ButcherTableau{
+    // a is an s by s matrix, which must be strictly lower triangular, and b and c have s entries
+    pub fn new(a: Vec<Vec<f64>>, b: Vec<f64>, c: Vec<f64>) -> Result<ButcherTableau, TableauError>{
+        let s = b.len();
+
+        if s == 0{
+            return Err(TableauError::Dimensions("b must have at least one entry"));
+        }
+        if c.len() != s{
+            return Err(TableauError::Dimensions("c must have one entry per stage"));
+        }
+        if a.len() != s || a.iter().any(|row| row.len() != s){
+            return Err(TableauError::Dimensions("a must be a square matrix with one row per stage"));
+        }
+        if a.iter().enumerate().any(|(i, row)| row[i..].iter().any(|&aij| aij != 0.0)){
+            return Err(TableauError::Implicit);
+        }
+
+        Ok(ButcherTableau{a, b, c})
+    }
+
+    // the explicit Euler method
+    pub fn euler() -> ButcherTableau{
+        ButcherTableau{a: vec![vec![0.0]], b: vec![1.0], c: vec![0.0]}
+    }
+
+    // Heun's method, the second order method used by rk2
+    pub fn heun() -> ButcherTableau{
+        ButcherTableau{a: vec![vec![0.0, 0.0], 
+                               vec![1.0, 0.0]], 
+                       b: vec![0.5, 0.5], 
+                       c: vec![0.0, 1.0]}
+    }
+
+    // the classical fourth order Runge-Kutta method
+    pub fn rk4() -> ButcherTableau{
+        ButcherTableau{a: vec![vec![0.0, 0.0, 0.0, 0.0], 
+                               vec![0.5, 0.0, 0.0, 0.0],
+                               vec![0.0, 0.5, 0.0, 0.0],
+                               vec![0.0, 0.0, 1.0, 0.0]], 
+                       b: vec![1.0/6.0, 1.0/3.0, 1.0/3.0, 1.0/6.0], 
+                       c: vec![0.0, 0.5, 0.5, 1.0]}
+    }
+}
+
+// a single step of the explicit Runge-Kutta method described by tableau. The stages are stored in
+// k, which is passed in so that its allocation can be reused from step to step
+fn rk_step<F: Fn(f64, f64) -> f64>(tableau: &ButcherTableau, k: &mut Vec<f64>, current: SolutionElement, deriv: &F, 
+                                   stepsize: f64) -> SolutionElement{
 
     let t0 = current.time;
     let y0 = current.val;
 
-    let d1 = deriv(y0);
-    let k1 = d1;
+    k.clear();
+
+    for (a_i, c_i) in tableau.a.iter().zip(&tableau.c){
+        let y_stage = y0 + stepsize*a_i.iter().zip(k.iter()).map(|(aij, kj)| aij*kj).sum::<f64>();
+        k.push(deriv(t0 + c_i*stepsize, y_stage));
+    }
+
+    let dy = tableau.b.iter().zip(k.iter()).map(|(bi, ki)| bi*ki).sum::<f64>();
+
+    let t1 = t0 + stepsize;
+    let y1 = y0 + stepsize*dy;
+
+    SolutionElement{ time: t1, val: y1}
+}
+
+// the second order Runge-Kutta method, as a stepper for SolutionSequence
+pub fn rk2_stepper<F: Fn(f64, f64) -> f64>() -> impl FnMut(SolutionElement, &F, f64) -> SolutionElement{
+    let tableau = ButcherTableau::heun();
+    let mut k   = Vec::with_capacity(2);
 
+    move |current, deriv, stepsize| rk_step(&tableau, &mut k, current, deriv, stepsize)
+}
+
+// the steps of the second order Runge-Kutta solution of y'(t) = deriv(t, y(t)) from initial
+pub fn rk2_sequence<F: Fn(f64, f64) -> f64>(deriv: F, stepsize: f64, initial: SolutionElement, finish_time: f64) 
+    -> SolutionSequence<F, impl FnMut(SolutionElement, &F, f64) -> SolutionElement>
+{
+    SolutionSequence::new(stepsize, initial, finish_time, deriv, rk2_stepper())
+}
+
+// the steps of the classical fourth order Runge-Kutta solution of y'(t) = deriv(t, y(t)) from initial
+pub fn rk4_sequence<F: Fn(f64, f64) -> f64>(deriv: F, stepsize: f64, initial: SolutionElement, finish_time: f64) 
+    -> SolutionSequence<F, impl FnMut(SolutionElement, &F, f64) -> SolutionElement>
+{
+    SolutionSequence::new(stepsize, initial, finish_time, deriv, rk4_next)
+}
+
+// the classical fourth order Runge-Kutta method, as a stepper for SolutionSequence
+pub fn rk4_next<F: Fn(f64, f64) -> f64>(current: SolutionElement, deriv: &F, stepsize: f64) -> SolutionElement{
+
+    let t0 = current.time;
+    let y0 = current.val;
+
+    let k1 = deriv(t0, y0);
+    let k2 = deriv(t0 + 0.5*stepsize, y0 + 0.5*stepsize*k1);
+    let k3 = deriv(t0 + 0.5*stepsize, y0 + 0.5*stepsize*k2);
+    let k4 = deriv(t0 + stepsize, y0 + stepsize*k3);
 
-    let d2 = k1;
-    let k2 = deriv(y0 + stepsize*d2);
-    
-    let dy = 0.5*(k1 + k2);
+    let dy = (k1 + 2.0*k2 + 2.0*k3 + k4)/6.0;
 
     let t1 = t0 + stepsize;
     let y1 = y0 + stepsize*dy;
@@ -49,12 +235,83 @@ fn rk2_next(current: SolutionElement, beta: f64, stepsize: f64) -> SolutionEleme
 }
 
 
-impl Iterator for SolutionSequence{
+// the signed step to take from time towards finish_time, which is negative if finish_time is 
+// earlier and is shortened to land exactly on finish_time, or None once finish_time has been 
+// reached. A stepsize that isn't positive would never get there, so it gives None straight away
+fn next_step(time: f64, stepsize: f64, finish_time: f64) -> Option<f64>{
+    let remaining = finish_time - time;
+
+    if remaining == 0.0 || remaining.is_nan() || stepsize.is_nan() || stepsize <= 0.0{
+        None
+    } else if remaining.abs() <= stepsize*(1.0 + STEP_SLACK){
+        Some(remaining)
+    } else {
+        Some(stepsize.copysign(remaining))
+    }
+}
+
+// the backward Euler method, y₁ = y₀ + h f(t₁, y₁). The implicit equation is solved by Newton's
+// method starting from y₀, with ∂f/∂y estimated by central differences
+fn backward_euler_next<F: Fn(f64, f64) -> f64>(current: SolutionElement, deriv: &F, stepsize: f64) -> SolutionElement{
+
+    let t1 = current.time + stepsize;
+    let y0 = current.val;
+
+    let mut y1 = y0;
+
+    for _i in 0..NEWTON_MAX_ITERS{
+        let dy    = f64::EPSILON.sqrt()*y1.abs().max(1.0);
+        let df_dy = (deriv(t1, y1 + dy) - deriv(t1, y1 - dy))/(2.0*dy);
+
+        let residual = y1 - y0 - stepsize*deriv(t1, y1);
+        let update   = residual/(1.0 - stepsize*df_dy);
+
+        y1 -= update;
+
+        // a NaN update can't be improved on either
+        if update.is_nan() || update.abs() <= NEWTON_TOL*y1.abs().max(1.0){
+            break;
+        }
+    }
+
+    SolutionElement{ time: t1, val: y1}
+}
+
+// a step of the trapezoidal rule, y₁ = y₀ + h/2 (f(t₀, y₀) + f(t₁, y₁)), with an explicit Euler 
+// predictor for y₁ followed by corrector_iters fixed point iterations of the implicit equation. 
+// With no corrector iterations this is the explicit Euler method, and with one it is Heun's method
+fn trapezoidal_next<F: Fn(f64, f64) -> f64>(current: SolutionElement, deriv: &F, stepsize: f64, 
+                                            corrector_iters: usize) -> SolutionElement{
+
+    let t0 = current.time;
+    let y0 = current.val;
+    let t1 = t0 + stepsize;
+
+    let f0 = deriv(t0, y0);
+
+    let mut y1 = y0 + stepsize*f0;
+
+    for _i in 0..corrector_iters{
+        y1 = y0 + 0.5*stepsize*(f0 + deriv(t1, y1));
+    }
+
+    SolutionElement{ time: t1, val: y1}
+}
+
+impl<F, S> Iterator for SolutionSequence<F, S>
+    where S: FnMut(SolutionElement, &F, f64) -> SolutionElement
+{
 
     type Item = SolutionElement;
 
     fn next(&mut self) -> Option<Self::Item>{
-       let next_soln_elem = rk2_next(self.soln_elem, self.beta, self.stepsize);
+       let step = next_step(self.soln_elem.time, self.stepsize, self.finish_time)?;
+
+       let mut next_soln_elem = (self.stepper)(self.soln_elem, &self.deriv, step);
+
+       if step.abs() != self.stepsize{
+           next_soln_elem.time = self.finish_time;
+       }
 
        self.soln_elem =  next_soln_elem;
   
@@ -63,15 +320,752 @@ impl Iterator for SolutionSequence{
 }
         
    
+// the state of a system of odes at a point in time
+#[derive(Debug, Clone)]
+struct SystemElement{
+    time : f64,
+    val  : Vec<f64>,
+}
 
-pub fn rk2(beta: f64, stepsize: f64, finish_time :f64) -> f64{
-    let mut soln_seq = SolutionSequence::new(stepsize, beta);
+// the steps of the solution of a system, as SolutionSequence is for a single ode
+struct SystemSequence<F>{
+    stepsize    : f64,
+    finish_time : f64,
+    deriv       : F,
+    soln_elem   : SystemElement,
+    stepper     : fn(&SystemElement, &F, f64) -> SystemElement,
+}
+
+// y + a*dy, element-wise
+fn axpy(y: &[f64], a: f64, dy: &[f64]) -> Vec<f64>{
+    y.iter().zip(dy).map(|(yi, dyi)| yi + a*dyi).collect()
+}
+
+fn rk2_system_next<F: Fn(f64, &[f64]) -> Vec<f64>>(current: &SystemElement, deriv: &F, stepsize: f64) -> SystemElement{
+
+    let t0 = current.time;
+    let y0 = &current.val;
+
+    let k1 = deriv(t0, y0);
+    let k2 = deriv(t0 + stepsize, &axpy(y0, stepsize, &k1));
+
+    let dy: Vec<f64> = k1.iter().zip(&k2).map(|(k1i, k2i)| 0.5*(k1i + k2i)).collect();
+
+    let t1 = t0 + stepsize;
+    let y1 = axpy(y0, stepsize, &dy);
+
+    SystemElement{ time: t1, val: y1}
+}
+
+fn rk4_system_next<F: Fn(f64, &[f64]) -> Vec<f64>>(current: &SystemElement, deriv: &F, stepsize: f64) -> SystemElement{
+
+    let t0 = current.time;
+    let y0 = &current.val;
+
+    let k1 = deriv(t0, y0);
+    let k2 = deriv(t0 + 0.5*stepsize, &axpy(y0, 0.5*stepsize, &k1));
+    let k3 = deriv(t0 + 0.5*stepsize, &axpy(y0, 0.5*stepsize, &k2));
+    let k4 = deriv(t0 + stepsize, &axpy(y0, stepsize, &k3));
 
-    let n = (finish_time/ stepsize) as usize;
+    let dy: Vec<f64> = (0..y0.len()).map(|i| (k1[i] + 2.0*k2[i] + 2.0*k3[i] + k4[i])/6.0).collect();
+
+    let t1 = t0 + stepsize;
+    let y1 = axpy(y0, stepsize, &dy);
+
+    SystemElement{ time: t1, val: y1}
+}
+
+impl<F: Fn(f64, &[f64]) -> Vec<f64>> Iterator for SystemSequence<F>{
+
+    type Item = SystemElement;
+
+    fn next(&mut self) -> Option<Self::Item>{
+       let step = next_step(self.soln_elem.time, self.stepsize, self.finish_time)?;
+
+       self.soln_elem = (self.stepper)(&self.soln_elem, &self.deriv, step);
+
+       if step.abs() != self.stepsize{
+           self.soln_elem.time = self.finish_time;
+       }
   
-    soln_seq.nth(n).unwrap().val
+       Some(self.soln_elem.clone())
+    }
+}
+
+
+// The fixed stepsize solvers integrate backwards in time if the finish time is before the initial
+// time. They return the initial condition, without taking a step, if the stepsize isn't positive;
+// rk2_checked reports that as an error instead
+pub fn rk2(beta: f64, stepsize: f64, finish_time :f64) -> f64{
+    rk2_from(beta, stepsize, T0, Y0, finish_time)
+}
+
+// rk2, checking that the stepsize and finish time make sense
+pub fn rk2_checked(beta: f64, stepsize: f64, finish_time :f64) -> Result<f64, OdeError>{
+    if !stepsize.is_finite() || stepsize <= 0.0{
+        return Err(OdeError::InvalidStepsize);
+    }
+    if !finish_time.is_finite(){
+        return Err(OdeError::InvalidFinishTime);
+    }
+
+    Ok(rk2(beta, stepsize, finish_time))
+}
+
+pub fn rk4(beta: f64, stepsize: f64, finish_time :f64) -> f64{
+    rk4_from(beta, stepsize, T0, Y0, finish_time)
+}
+
+// rk2 with the initial condition y(t0) = y0
+pub fn rk2_from(beta: f64, stepsize: f64, t0: f64, y0: f64, finish_time :f64) -> f64{
+    let initial = SolutionElement{time: t0, val: y0};
+
+    SolutionSequence::new(stepsize, initial, finish_time, |_, y| beta*y, rk2_stepper()).last().unwrap_or(initial).val
+}
+
+// rk4 with the initial condition y(t0) = y0
+pub fn rk4_from(beta: f64, stepsize: f64, t0: f64, y0: f64, finish_time :f64) -> f64{
+    let initial = SolutionElement{time: t0, val: y0};
+
+    SolutionSequence::new(stepsize, initial, finish_time, |_, y| beta*y, rk4_next).last().unwrap_or(initial).val
+}
+
+// rk2_from, also returning the number of steps taken
+pub fn rk2_from_counted(beta: f64, stepsize: f64, t0: f64, y0: f64, finish_time :f64) -> (f64, usize){
+    let initial  = SolutionElement{time: t0, val: y0};
+    let soln_seq = SolutionSequence::new(stepsize, initial, finish_time, |_, y| beta*y, rk2_stepper());
+
+    soln_seq.fold((initial.val, 0), |(_, steps), elem| (elem.val, steps + 1))
+}
+
+// rk4_from, also returning the number of steps taken
+pub fn rk4_from_counted(beta: f64, stepsize: f64, t0: f64, y0: f64, finish_time :f64) -> (f64, usize){
+    let initial  = SolutionElement{time: t0, val: y0};
+    let soln_seq = SolutionSequence::new(stepsize, initial, finish_time, |_, y| beta*y, rk4_next);
+
+    soln_seq.fold((initial.val, 0), |(_, steps), elem| (elem.val, steps + 1))
+}
+
+// An objective for fitting the parameter of an ode to its analytic solution: the squared error at
+// final_time of integrator(x, h, final_time), which solves the ode with parameter x and stepsize h 
+// as rk2 does, against the analytic solution target_fn(final_time). The whole problem is captured
+// by the closure, so nothing has to be held in globals to fit the signature of an objective, e.g.
+//
+//     let mse = mse_against_analytic(rk2, |t| t.exp(), 5.0);
+//     let fit = implicit_filtering(mse, 1.5, 0.1, 1e-7)?;
+pub fn mse_against_analytic<I, A>(integrator: I, target_fn: A, final_time: f64) -> impl Fn(f64, f64) -> f64
+    where I: Fn(f64, f64, f64) -> f64,
+          A: Fn(f64) -> f64
+{
+    move |x, h| (target_fn(final_time) - integrator(x, h, final_time)).powi(2)
+}
+
+// An objective for fitting an ode solver to a known value, e.g. 
+//
+//     let solver  = |beta, h| rk2_from_counted(beta, h, 0.0, 1.0, 5.0);
+//     let mut fit = OdeObjective::new(solver, 5.0_f64.exp());
+//     let report  = implicit_filtering_report(&mut fit, 1.5, 0.1, 1e-7, &FilterConfig::default())?;
+//
+// solver(x, h) integrates with parameter x and stepsize h, returning the solution together with 
+// the number of steps it took, and the objective is the squared error of the solution against 
+// target. The steps taken over a run are reported as FilterReport::objective_work
+pub struct OdeObjective<S>{
+    solver : S,
+    target : f64,
+    steps  : usize,
+}
+
+impl<S> OdeObjective<S>
+    where S: FnMut(f64, f64) -> (f64, usize)
+{
+    pub fn new(solver: S, target: f64) -> OdeObjective<S>{
+        OdeObjective{solver, target, steps: 0}
+    }
+
+    // the number of steps taken by the solver so far
+    pub fn steps(&self) -> usize{
+        self.steps
+    }
+
+    // the solver and the number of steps it has taken
+    pub fn into_parts(self) -> (S, usize){
+        (self.solver, self.steps)
+    }
+}
+
+impl<S> Objective<f64> for OdeObjective<S>
+    where S: FnMut(f64, f64) -> (f64, usize)
+{
+    fn mse(&mut self, x: f64, h: f64) -> f64{
+        let (estimate, steps) = (self.solver)(x, h);
+        self.steps += steps;
+
+        (self.target - estimate).powi(2)
+    }
+
+    fn work(&self) -> Option<usize>{
+        Some(self.steps)
+    }
+}
+
+impl<S> Objective<f64> for &mut OdeObjective<S>
+    where S: FnMut(f64, f64) -> (f64, usize)
+{
+    fn mse(&mut self, x: f64, h: f64) -> f64{
+        (**self).mse(x, h)
+    }
+
+    fn work(&self) -> Option<usize>{
+        (**self).work()
+    }
+}
+
+// the same as rk2, except that every step of the solution is returned, starting with the initial
+// condition
+pub fn rk2_trajectory(beta: f64, stepsize: f64, finish_time :f64) -> Vec<SolutionElement>{
+    let soln_seq = SolutionSequence::new(stepsize, INITIAL_CONDITION, finish_time, |_, y| beta*y, rk2_stepper());
 
+    std::iter::once(INITIAL_CONDITION).chain(soln_seq).collect()
 }
 
+// the solution of rk2 at each of the given times from a single integration, taking the values
+// between steps by linear interpolation. The integration runs as far as the latest time, and the
+// results are in the same order as times. A time before the initial time (or NaN) gives None
+pub fn rk2_at_times(beta: f64, stepsize: f64, times: &[f64]) -> Vec<Option<f64>>{
+    let mut order: Vec<usize> = (0..times.len()).filter(|&i| times[i] >= T0).collect();
+    order.sort_by(|&i, &j| times[i].partial_cmp(&times[j]).unwrap());
 
+    let mut result = vec![None; times.len()];
 
+    let finish_time = match order.last(){
+        Some(&i) => times[i],
+        None     => return result,
+    };
+
+    let mut soln_seq = SolutionSequence::new(stepsize, INITIAL_CONDITION, finish_time, |_, y| beta*y, rk2_stepper());
+    let mut prev     = INITIAL_CONDITION;
+    let mut current  = INITIAL_CONDITION;
+
+    for i in order{
+        while current.time < times[i]{
+            match soln_seq.next(){
+                Some(next) => {prev = current; current = next},
+                None       => break,
+            }
+        }
+
+        let val = if current.time == prev.time {current.val} else {
+            let frac = (times[i] - prev.time)/(current.time - prev.time);
+
+            prev.val + frac.min(1.0)*(current.val - prev.val)
+        };
+
+        result[i] = Some(val);
+    }
+
+    result
+}
+
+// the order of accuracy of solver, which is called like rk2, found from the error against the exact 
+// solution of y' = βy at each of the stepsizes. The order is the slope of the least squares line 
+// through the points (ln h, ln error). Stepsizes with no error, at which the solver is exact to 
+// rounding, carry no information and are skipped. NaN if fewer than two stepsizes are left
+pub fn estimate_order<S: Fn(f64, f64, f64) -> f64>(solver: S, beta: f64, finish_time: f64, stepsizes: &[f64]) -> f64{
+    let exact = Y0*(beta*(finish_time - T0)).exp();
+
+    let points: Vec<(f64, f64)> = stepsizes.iter()
+                                           .map(|&h| (h.ln(), (solver(beta, h, finish_time) - exact).abs().ln()))
+                                           .filter(|(ln_h, ln_err)| ln_h.is_finite() && ln_err.is_finite())
+                                           .collect();
+
+    if points.len() < 2{
+        return f64::NAN;
+    }
+
+    let n       = points.len() as f64;
+    let mean_h  = points.iter().map(|p| p.0).sum::<f64>()/n;
+    let mean_e  = points.iter().map(|p| p.1).sum::<f64>()/n;
+    let cov     = points.iter().map(|p| (p.0 - mean_h)*(p.1 - mean_e)).sum::<f64>();
+    let var     = points.iter().map(|p| (p.0 - mean_h).powi(2)).sum::<f64>();
+
+    cov/var
+}
+
+// solve y'(t) = deriv(t, y(t)) with the second order Runge-Kutta method
+pub fn rk2_with<F: Fn(f64, f64) -> f64>(deriv: F, stepsize: f64, finish_time :f64) -> f64{
+    let soln_seq = SolutionSequence::new(stepsize, INITIAL_CONDITION, finish_time, deriv, rk2_stepper());
+
+    soln_seq.last().unwrap_or(INITIAL_CONDITION).val
+
+}
+
+// integrate y'(t) = deriv(t, y(t)) with initial condition y(0) = y0 using the second order 
+// Runge-Kutta method until y crosses target, returning the point at which it does so. The 
+// crossing is located by linear interpolation between the steps either side of it. If y doesn't
+// reach target by max_time, the result is None
+pub fn rk2_until<F: Fn(f64, f64) -> f64>(deriv: F, y0: f64, stepsize: f64, target: f64, max_time: f64) 
+    -> Option<SolutionElement>
+{
+    let initial = SolutionElement{time: T0, val: y0};
+
+    if y0 == target{
+        return Some(initial);
+    }
+
+    let mut prev = initial;
+
+    for current in SolutionSequence::new(stepsize, initial, max_time, deriv, rk2_stepper()){
+        if (prev.val - target)*(current.val - target) <= 0.0{
+            let frac = (target - prev.val)/(current.val - prev.val);
+
+            return Some(SolutionElement{time: prev.time + frac*(current.time - prev.time), val: target});
+        }
+
+        prev = current;
+    }
+
+    None
+}
+
+// solve y'(t) = deriv(t, y(t)) with the classical fourth order Runge-Kutta method
+pub fn rk4_with<F: Fn(f64, f64) -> f64>(deriv: F, stepsize: f64, finish_time :f64) -> f64{
+    let soln_seq = SolutionSequence::new(stepsize, INITIAL_CONDITION, finish_time, deriv, rk4_next);
+
+    soln_seq.last().unwrap_or(INITIAL_CONDITION).val
+
+}
+
+// solve y'(t) = deriv(t, y(t)) with the explicit Runge-Kutta method described by tableau
+pub fn rk_with_tableau<F: Fn(f64, f64) -> f64>(tableau: &ButcherTableau, deriv: F, stepsize: f64, finish_time :f64) -> f64{
+    let mut k    = Vec::with_capacity(tableau.b.len());
+    let soln_seq = SolutionSequence::new(stepsize, INITIAL_CONDITION, finish_time, deriv, 
+                                         |current, deriv: &F, h| rk_step(tableau, &mut k, current, deriv, h));
+
+    soln_seq.last().unwrap_or(INITIAL_CONDITION).val
+
+}
+
+// solve y'(t) = deriv(t, y(t)) with initial condition y(0) = y0 using the backward Euler method.
+// This is only first order, but it is stable for stiff problems at stepsizes where the explicit 
+// methods blow up
+pub fn backward_euler<F: Fn(f64, f64) -> f64>(deriv: F, y0: f64, stepsize: f64, finish_time :f64) -> f64{
+    let initial  = SolutionElement{time: T0, val: y0};
+    let soln_seq = SolutionSequence::new(stepsize, initial, finish_time, deriv, backward_euler_next);
+
+    soln_seq.last().unwrap_or(initial).val
+
+}
+
+// solve y'(t) = deriv(t, y(t)) with initial condition y(0) = y0 using the trapezoidal rule as a
+// predictor-corrector method. The fixed point iteration converges to the implicit trapezoidal 
+// solution as corrector_iters grows, provided the stepsize is small enough that |h/2 ∂f/∂y| < 1
+pub fn trapezoidal<F: Fn(f64, f64) -> f64>(deriv: F, y0: f64, stepsize: f64, finish_time :f64, corrector_iters: usize) 
+    -> f64
+{
+    let initial  = SolutionElement{time: T0, val: y0};
+    let stepper  = |current, deriv: &F, stepsize| trapezoidal_next(current, deriv, stepsize, corrector_iters);
+    let soln_seq = SolutionSequence::new(stepsize, initial, finish_time, deriv, stepper);
+
+    soln_seq.last().unwrap_or(initial).val
+}
+
+// solve the system y'(t) = deriv(t, y(t)) with initial condition y(0) = y0 using the second order
+// Runge-Kutta method, returning the state at finish_time
+pub fn rk2_system<F: Fn(f64, &[f64]) -> Vec<f64>>(deriv: F, y0: &[f64], stepsize: f64, finish_time :f64) -> Vec<f64>{
+    let soln_seq = SystemSequence{stepsize, finish_time, deriv, soln_elem: SystemElement{time: T0, val: y0.to_vec()},
+                                  stepper: rk2_system_next};
+
+    soln_seq.last().map_or_else(|| y0.to_vec(), |elem| elem.val)
+
+}
+
+// rk2_system with the classical fourth order Runge-Kutta method
+pub fn rk4_system<F: Fn(f64, &[f64]) -> Vec<f64>>(deriv: F, y0: &[f64], stepsize: f64, finish_time :f64) -> Vec<f64>{
+    let soln_seq = SystemSequence{stepsize, finish_time, deriv, soln_elem: SystemElement{time: T0, val: y0.to_vec()},
+                                  stepper: rk4_system_next};
+
+    soln_seq.last().map_or_else(|| y0.to_vec(), |elem| elem.val)
+}
+
+// the same as rk2_system, but also returns the largest absolute drift of invariant(y) from its
+// initial value over every step, as a check on the accuracy of the solution for conservative
+// systems. An invariant that depends on time can be monitored by including t in the state with t' = 1
+pub fn rk2_system_monitored<F, I>(deriv: F, y0: &[f64], stepsize: f64, finish_time :f64, invariant: I)
+    -> (Vec<f64>, f64)
+    where F: Fn(f64, &[f64]) -> Vec<f64>,
+          I: Fn(&[f64]) -> f64
+{
+    let soln_seq = SystemSequence{stepsize, finish_time, deriv, soln_elem: SystemElement{time: T0, val: y0.to_vec()},
+                                  stepper: rk2_system_next};
+
+    monitor_drift(soln_seq, y0, invariant)
+}
+
+// rk2_system_monitored with the classical fourth order Runge-Kutta method
+pub fn rk4_system_monitored<F, I>(deriv: F, y0: &[f64], stepsize: f64, finish_time :f64, invariant: I)
+    -> (Vec<f64>, f64)
+    where F: Fn(f64, &[f64]) -> Vec<f64>,
+          I: Fn(&[f64]) -> f64
+{
+    let soln_seq = SystemSequence{stepsize, finish_time, deriv, soln_elem: SystemElement{time: T0, val: y0.to_vec()},
+                                  stepper: rk4_system_next};
+
+    monitor_drift(soln_seq, y0, invariant)
+}
+
+// the final state of soln_seq, and the largest absolute drift of invariant from its value at y0
+fn monitor_drift<F, I>(soln_seq: SystemSequence<F>, y0: &[f64], invariant: I) -> (Vec<f64>, f64)
+    where F: Fn(f64, &[f64]) -> Vec<f64>,
+          I: Fn(&[f64]) -> f64
+{
+    let initial   = invariant(y0);
+    let mut val   = y0.to_vec();
+    let mut drift = 0.0_f64;
+
+    for elem in soln_seq{
+        // a NaN invariant means the drift can't be bounded, so it's kept rather than ignored by max
+        let current = (invariant(&elem.val) - initial).abs();
+        if current.is_nan() || current > drift{
+            drift = current;
+        }
+        val   = elem.val;
+    }
+
+    (val, drift)
+}
+
+// The value at finish_time found by rk45, together with how many steps it took to get there
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveSolution{
+    pub val      : f64,
+    pub accepted : usize,
+    pub rejected : usize,
+}
+
+// the Dormand-Prince tableau. The fifth order weights are the final row of A, so the last stage
+// is evaluated at the fifth order solution
+const DP_C : [f64; 7] = [0.0, 1.0/5.0, 3.0/10.0, 4.0/5.0, 8.0/9.0, 1.0, 1.0];
+const DP_A : [[f64; 6]; 7] = [
+    [0.0,            0.0,             0.0,            0.0,          0.0,             0.0],
+    [1.0/5.0,        0.0,             0.0,            0.0,          0.0,             0.0],
+    [3.0/40.0,       9.0/40.0,        0.0,            0.0,          0.0,             0.0],
+    [44.0/45.0,      -56.0/15.0,      32.0/9.0,       0.0,          0.0,             0.0],
+    [19372.0/6561.0, -25360.0/2187.0, 64448.0/6561.0, -212.0/729.0, 0.0,             0.0],
+    [9017.0/3168.0,  -355.0/33.0,     46732.0/5247.0, 49.0/176.0,   -5103.0/18656.0, 0.0],
+    [35.0/384.0,     0.0,             500.0/1113.0,   125.0/192.0,  -2187.0/6784.0,  11.0/84.0],
+];
+const DP_B5 : [f64; 7] = [35.0/384.0, 0.0, 500.0/1113.0, 125.0/192.0, -2187.0/6784.0, 11.0/84.0, 0.0];
+// the embedded fourth order weights
+const DP_B4 : [f64; 7] = [5179.0/57600.0, 0.0, 7571.0/16695.0, 393.0/640.0, -92097.0/339200.0, 187.0/2100.0, 1.0/40.0];
+
+// a single Dormand-Prince step, returning the fifth order solution and an estimate of its error
+fn rk45_step<F: Fn(f64, f64) -> f64>(deriv: &F, t: f64, y: f64, stepsize: f64) -> (f64, f64){
+    let mut k = [0.0; 7];
+
+    for i in 0..7{
+        let y_stage = y + stepsize*(0..i).map(|j| DP_A[i][j]*k[j]).sum::<f64>();
+        k[i] = deriv(t + DP_C[i]*stepsize, y_stage);
+    }
+
+    let y_new = y + stepsize*(0..7).map(|j| DP_B5[j]*k[j]).sum::<f64>();
+    let err   = stepsize*(0..7).map(|j| (DP_B5[j] - DP_B4[j])*k[j]).sum::<f64>();
+
+    (y_new, err.abs())
+}
+
+// solve y'(t) = deriv(t, y(t)) with initial condition y(t0) = y0 using the adaptive Dormand-Prince
+// method. A step is accepted when its estimated error is no more than tol*(1 + |y|), and the
+// stepsize is adjusted after every step to keep the error near that bound. If the tolerance is so
+// tight, or the solution so badly behaved, that finish_time isn't reached within the step limit,
+// the result is an error rather than the solution at some earlier time
+pub fn rk45<F: Fn(f64, f64) -> f64>(deriv: F, y0: f64, t0: f64, finish_time: f64, tol: f64) 
+    -> Result<AdaptiveSolution, OdeError>
+{
+    if tol.is_nan() || tol <= 0.0{
+        return Err(OdeError::InvalidTolerance);
+    }
+    if !finish_time.is_finite() || finish_time < t0{
+        return Err(OdeError::InvalidFinishTime);
+    }
+
+    let mut t        = t0;
+    let mut y        = y0;
+    let mut stepsize = 0.01*(finish_time - t0);
+    let mut accepted = 0;
+    let mut rejected = 0;
+
+    while t < finish_time && accepted + rejected < RK45_MAX_STEPS{
+        // land exactly on finish_time
+        let h = stepsize.min(finish_time - t);
+
+        let (y_new, err) = rk45_step(&deriv, t, y, h);
+        let scale        = tol*(1.0 + y.abs().max(y_new.abs()));
+
+        if err <= scale{
+            t = if h == finish_time - t {finish_time} else {t + h};
+            y = y_new;
+            accepted += 1;
+        } else {
+            rejected += 1;
+        }
+
+        // the usual safety factor, with the change in stepsize limited to a factor of five
+        let factor = if err == 0.0 {5.0} else {0.9*(scale/err).powf(0.2)};
+        stepsize   = h*if factor.is_nan() {0.2} else {factor.clamp(0.2, 5.0)};
+    }
+
+    if t < finish_time{
+        return Err(OdeError::StepLimitReached{time: t});
+    }
+
+    Ok(AdaptiveSolution{val: y, accepted, rejected})
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn rk45_meets_its_tolerance(){
+        let exact = 5.0_f64.exp();
+
+        for &tol in &[1e-4, 1e-7, 1e-10]{
+            let soln = rk45(|_, y| y, 1.0, 0.0, 5.0, tol).unwrap();
+
+            assert!((soln.val - exact).abs() <= 10.0*tol*exact, "tol {}: error {}", tol, soln.val - exact);
+            assert!(soln.accepted > 0);
+        }
+    }
+
+    #[test]
+    fn rk45_reports_the_step_limit(){
+        match rk45(|_, y| y, 1.0, 0.0, 5.0, 1e-300){
+            Err(OdeError::StepLimitReached{time}) => assert!(time < 5.0),
+            other                                 => panic!("expected the step limit, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rk45_rejects_bad_arguments(){
+        assert_eq!(rk45(|_, y| y, 1.0, 0.0, 5.0, 0.0), Err(OdeError::InvalidTolerance));
+        assert_eq!(rk45(|_, y| y, 1.0, 0.0, 5.0, -1e-6), Err(OdeError::InvalidTolerance));
+        assert_eq!(rk45(|_, y| y, 1.0, 0.0, 5.0, f64::NAN), Err(OdeError::InvalidTolerance));
+        assert_eq!(rk45(|_, y| y, 1.0, 0.0, f64::INFINITY, 1e-6), Err(OdeError::InvalidFinishTime));
+        assert_eq!(rk45(|_, y| y, 1.0, 1.0, 0.0, 1e-6), Err(OdeError::InvalidFinishTime));
+    }
+
+    #[test]
+    fn public_steppers_match_rk2_and_rk4(){
+        let rk2_last = SolutionSequence::new(0.01, INITIAL_CONDITION, 5.0, |_, y| 1.3*y, rk2_stepper()).last().unwrap();
+        let rk4_last = SolutionSequence::new(0.01, INITIAL_CONDITION, 5.0, |_, y| 1.3*y, rk4_next).last().unwrap();
+
+        assert_eq!(rk2_last.val, rk2(1.3, 0.01, 5.0));
+        assert_eq!(rk4_last.val, rk4(1.3, 0.01, 5.0));
+        assert_eq!(rk2_last.time, 5.0);
+    }
+
+    #[test]
+    fn rk4_conserves_energy_better_than_rk2(){
+        let oscillator = |_t: f64, y: &[f64]| vec![y[1], -y[0]];
+        let energy     = |y: &[f64]| 0.5*(y[0]*y[0] + y[1]*y[1]);
+
+        let (rk2_end, rk2_drift) = rk2_system_monitored(oscillator, &[1.0, 0.0], 0.1, 10.0, energy);
+        let (rk4_end, rk4_drift) = rk4_system_monitored(oscillator, &[1.0, 0.0], 0.1, 10.0, energy);
+
+        assert!(rk4_drift < 1e-3*rk2_drift, "rk4 drift {} against rk2 drift {}", rk4_drift, rk2_drift);
+        assert!(rk2_drift > 0.0);
+        assert_eq!(rk4_end, rk4_system(oscillator, &[1.0, 0.0], 0.1, 10.0));
+        assert_eq!(rk2_end, rk2_system(oscillator, &[1.0, 0.0], 0.1, 10.0));
+        assert!((rk4_end[0] - 10f64.cos()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rk4_is_more_accurate_than_rk2(){
+        let exact = 5.0_f64.exp();
+
+        let rk2_error = (rk2(1.0, 0.1, 5.0) - exact).abs();
+        let rk4_error = (rk4(1.0, 0.1, 5.0) - exact).abs();
+
+        assert!(rk4_error < rk2_error/100.0, "rk4 error {} against rk2 error {}", rk4_error, rk2_error);
+    }
+
+    #[test]
+    fn rk2_with_integrates_decay(){
+        for &t in &[0.5, 1.0, 3.0]{
+            assert!((rk2_with(|_, y| -y, 1e-3, t) - (-t).exp()).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn rk2_system_solves_the_harmonic_oscillator(){
+        // y'' = -y with y(0) = 1, y'(0) = 0, so y = cos(t) and y' = -sin(t)
+        let end = rk2_system(|_, y| vec![y[1], -y[0]], &[1.0, 0.0], 1e-3, core::f64::consts::PI);
+
+        assert!((end[0] + 1.0).abs() < 1e-5);
+        assert!(end[1].abs() < 1e-5);
+    }
+
+    #[test]
+    fn rk2_trajectory_runs_from_the_initial_condition_to_rk2(){
+        let trajectory = rk2_trajectory(1.3, 0.1, 5.0);
+
+        assert_eq!(trajectory[0], INITIAL_CONDITION);
+        assert_eq!(trajectory.last().unwrap().val, rk2(1.3, 0.1, 5.0));
+        assert_eq!(trajectory.len(), 51);
+    }
+
+    #[test]
+    fn non_dividing_stepsize_ends_at_finish_time(){
+        let trajectory = rk2_trajectory(1.0, 0.3, 5.0);
+        let last       = trajectory.last().unwrap();
+
+        assert_eq!(last.time, 5.0);
+        assert_eq!(trajectory.len(), 18);
+        // the last step is the partial one from t = 4.8
+        let h = 5.0 - trajectory[16].time;
+        assert!((h - 0.2).abs() < 1e-12);
+        assert!((last.val - trajectory[16].val*(1.0 + h + 0.5*h*h)).abs() < 1e-12*last.val);
+    }
+
+    #[test]
+    fn rk2_from_starts_at_its_initial_condition(){
+        let exact = 2.0*(0.7_f64*(3.0 - 1.0)).exp();
+
+        assert!((rk2_from(0.7, 1e-3, 1.0, 2.0, 3.0) - exact).abs() < 1e-5*exact);
+        assert_eq!(rk2_from(0.7, 0.1, 0.0, 1.0, 5.0), rk2(0.7, 0.1, 5.0));
+    }
+
+    #[test]
+    fn backward_euler_is_stable_where_rk2_is_not(){
+        // with beta*h = -5 each rk2 step multiplies y by 1 - 5 + 12.5, while backward Euler divides it by 6
+        let implicit = backward_euler(|_, y| -50.0*y, 1.0, 0.1, 5.0);
+        let explicit = rk2_with(|_, y| -50.0*y, 0.1, 5.0);
+
+        assert!(implicit.abs() < 1e-10);
+        assert!(explicit.abs() > 1e10);
+        assert!((backward_euler(|_, y| -50.0*y, 1.0, 0.1, 0.1) - 1.0/6.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn tableaus_reproduce_their_methods(){
+        // one explicit Euler step multiplies y by 1 + beta*h
+        assert_eq!(rk_with_tableau(&ButcherTableau::euler(), |_, y| 2.0*y, 0.5, 0.5), 2.0);
+        assert_eq!(rk_with_tableau(&ButcherTableau::heun(), |_, y| 1.3*y, 0.1, 5.0), rk2(1.3, 0.1, 5.0));
+
+        let rk4_error = (rk_with_tableau(&ButcherTableau::rk4(), |_, y| y, 0.1, 5.0) - 5.0_f64.exp()).abs();
+        assert!(rk4_error < 1e-3);
+        assert!((rk_with_tableau(&ButcherTableau::rk4(), |_, y| 1.3*y, 0.1, 5.0) - rk4(1.3, 0.1, 5.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn malformed_tableaus_are_rejected(){
+        assert!(ButcherTableau::new(vec![vec![0.0]], vec![1.0], vec![0.0]).is_ok());
+        assert!(matches!(ButcherTableau::new(vec![], vec![], vec![]), Err(TableauError::Dimensions(_))));
+        assert!(matches!(ButcherTableau::new(vec![vec![0.0]], vec![1.0], vec![0.0, 1.0]), Err(TableauError::Dimensions(_))));
+        assert_eq!(ButcherTableau::new(vec![vec![0.5]], vec![1.0], vec![0.5]).err(), Some(TableauError::Implicit));
+    }
+
+    #[test]
+    fn rk2_until_locates_the_crossing(){
+        // y = e^t reaches 10 at t = ln 10
+        let crossing = rk2_until(|_, y| y, 1.0, 1e-3, 10.0, 5.0).unwrap();
+
+        assert_eq!(crossing.val, 10.0);
+        assert!((crossing.time - 10f64.ln()).abs() < 1e-5);
+
+        assert_eq!(rk2_until(|_, y| y, 10.0, 1e-3, 10.0, 5.0), Some(SolutionElement{time: 0.0, val: 10.0}));
+        assert_eq!(rk2_until(|_, y| y, 1.0, 1e-3, 1e6, 5.0), None);
+    }
+
+    #[test]
+    fn rk2_at_times_samples_a_single_integration(){
+        let values = rk2_at_times(1.0, 1e-3, &[2.5, 1.0, 5.0, -1.0]);
+
+        for (value, &t) in values.iter().zip(&[2.5, 1.0, 5.0]){
+            assert!((value.unwrap() - f64::exp(t)).abs() < 1e-5*f64::exp(t));
+        }
+        assert_eq!(values[2], Some(rk2(1.0, 1e-3, 5.0)));
+        assert_eq!(values[3], None);
+    }
+
+    #[test]
+    fn degenerate_stepsizes_and_finish_times(){
+        assert_eq!(rk2_checked(1.0, 0.0, 5.0), Err(OdeError::InvalidStepsize));
+        assert_eq!(rk2_checked(1.0, -0.1, 5.0), Err(OdeError::InvalidStepsize));
+        assert_eq!(rk2_checked(1.0, f64::NAN, 5.0), Err(OdeError::InvalidStepsize));
+        assert_eq!(rk2_checked(1.0, 0.1, f64::INFINITY), Err(OdeError::InvalidFinishTime));
+        assert_eq!(rk2(1.0, 0.0, 5.0), Y0);
+
+        // a negative finish time integrates backwards
+        let back = rk2_checked(1.0, 1e-3, -1.0).unwrap();
+        assert!((back - (-1.0_f64).exp()).abs() < 1e-6);
+        assert_eq!(back, rk2(1.0, 1e-3, -1.0));
+    }
+
+    #[test]
+    fn trapezoidal_correctors_approach_the_implicit_rule(){
+        // each step of the implicit trapezoidal rule on y' = -2y multiplies y by (1 - h)/(1 + h)
+        let h     = 0.1_f64;
+        let limit = ((1.0 - h)/(1.0 + h)).powi(20);
+
+        let errors: Vec<f64> = [0, 1, 2, 4, 8].iter()
+                                              .map(|&iters| (trapezoidal(|_, y| -2.0*y, 1.0, h, 2.0, iters) - limit).abs())
+                                              .collect();
+
+        assert!(errors.windows(2).all(|pair| pair[1] < pair[0]), "errors {:?}", errors);
+        assert!(errors[4] < 1e-6);
+    }
+
+    #[test]
+    fn rk2_integrates_backwards(){
+        let trajectory = rk2_trajectory(0.8, 1e-3, -3.0);
+        let last       = trajectory.last().unwrap();
+
+        assert_eq!(last.time, -3.0);
+        assert!((last.val - (0.8_f64*-3.0).exp()).abs() < 1e-7);
+        assert!(trajectory.windows(2).all(|pair| pair[1].time < pair[0].time));
+    }
+
+    #[test]
+    fn estimated_orders_match_the_methods(){
+        let stepsizes = [0.1, 0.05, 0.025, 0.0125];
+
+        assert!((estimate_order(rk2, 1.0, 2.0, &stepsizes) - 2.0).abs() < 0.2);
+        assert!((estimate_order(rk4, 1.0, 2.0, &stepsizes) - 4.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn ode_steps_grow_as_the_stencil_shrinks(){
+        let config    = crate::FilterConfig{verbose: false, max_outer_iters: 4, ..crate::FilterConfig::default()};
+        let mut calls = Vec::new();
+        let solver    = |beta, h| {let (val, steps) = rk2_from_counted(beta, h, 0.0, 1.0, 5.0);
+                                   calls.push((h, steps));
+                                   (val, steps)};
+        let mut fit   = OdeObjective::new(solver, 5.0_f64.exp());
+        let report    = crate::implicit_filtering_report(&mut fit, 1.5, 0.1, 1e-7, &config).unwrap();
+        let total     = fit.steps();
+
+        assert_eq!(report.objective_work, Some(total));
+        assert_eq!(total, calls.iter().map(|&(_, steps)| steps).sum::<usize>());
+
+        // each solve takes 5/h steps, so a quarter of the stencil size costs four times as many
+        assert!(calls.iter().all(|&(h, steps)| steps == (5.0/h).round() as usize));
+        let sizes = calls.iter().map(|&(h, _)| h).fold(Vec::new(), |mut sizes, h| {
+                        if sizes.last() != Some(&h) {sizes.push(h);}
+                        sizes
+                    });
+        assert_eq!(sizes, [0.1, 0.025, 0.00625, 0.0015625]);
+    }
+
+    #[test]
+    fn mse_against_analytic_recovers_beta(){
+        let config = crate::FilterConfig{verbose: false, max_outer_iters: 4, ..crate::FilterConfig::default()};
+        let mse    = mse_against_analytic(rk2, |t| t.exp(), 5.0);
+
+        assert_eq!(mse(1.2, 0.1), (5.0_f64.exp() - rk2(1.2, 0.1, 5.0)).powi(2));
+
+        // the error of rk2 at the stepsize moves the minimum away from 1 by O(h²)
+        let fit = crate::implicit_filtering_with(mse, 1.5, 0.1, 1e-7, &config).unwrap();
+        assert!((fit.x - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,139 @@
+
+// A pull-based interface to implicit_filtering_report, e.g.
+//
+//     let mut iter = ImplicitFilterIter::new(mse, 1.5, 0.1, 1e-7, &FilterConfig::default());
+//     for record in &mut iter { ... }
+//     let report = iter.report();
+//
+// Each call to next that finds no record waiting searches the next stencil size, so the run only
+// proceeds as fast as the records are consumed. The records of a stencil size are handed out once
+// the search at that size is complete, and are the same records a callback would have been shown
+
+use alloc::collections::VecDeque;
+
+use num_traits::Float;
+
+use crate::objective::{Counting, Negated};
+use crate::{ControlFlow, Diagnostics, FilterConfig, FilterReport, FilterState, IterationRecord, Objective,
+            OptimDirection, OptimError};
+
+pub struct ImplicitFilterIter<T, F>{
+    mse        : F,
+    x0         : T,
+    h0         : T,
+    tol        : T,
+    config     : FilterConfig,
+    // None until the first call to next, which is when the starting point is evaluated
+    state      : Option<FilterState<T>>,
+    eval_count : usize,
+    exhausted  : bool,
+    pending    : VecDeque<IterationRecord<T>>,
+    // set once the run has ended
+    outcome    : Option<Result<FilterReport<T>, OptimError>>,
+}
+
+impl<T, F> ImplicitFilterIter<T, F>
+    where T: Float, F: Objective<T>
+{
+    pub fn new(mse: F, x0: T, h0: T, tol: T, config: &FilterConfig) -> ImplicitFilterIter<T, F>{
+        ImplicitFilterIter{mse, x0, h0, tol,
+                           config:     config.clone(),
+                           state:      None,
+                           eval_count: 0,
+                           exhausted:  false,
+                           pending:    VecDeque::new(),
+                           outcome:    None}
+    }
+
+    // the outcome of the run, as implicit_filtering_report would have returned it, or None if the
+    // iterator hasn't yet reached the end
+    pub fn report(&self) -> Option<&Result<FilterReport<T>, OptimError>>{
+        self.outcome.as_ref()
+    }
+
+    // search the next stencil size, evaluating the starting point first if this is the first
+    fn advance(&mut self){
+        if self.state.is_none(){
+            if let Err(err) = self.config.validate(){
+                self.outcome = Some(Err(err));
+                return;
+            }
+        }
+
+        let ImplicitFilterIter{mse, x0, h0, tol, config, state, eval_count, exhausted, pending, outcome} = self;
+
+        *outcome = match config.direction{
+            OptimDirection::Minimize => {
+                let mut counting = Counting{inner: mse, count: *eval_count, budget: config.max_evals, exhausted: *exhausted};
+                let result       = advance_state(state, &mut counting, *x0, *h0, *tol, config,
+                                                 &mut |record| pending.push_back(*record));
+
+                *eval_count = counting.count;
+                *exhausted  = counting.exhausted;
+                result
+            },
+            // as in filter_to, a maximum is found by minimising the negated objective
+            OptimDirection::Maximize => {
+                let mut negated  = Negated(mse);
+                let mut counting = Counting{inner: &mut negated, count: *eval_count, budget: config.max_evals,
+                                            exhausted: *exhausted};
+                let result       = advance_state(state, &mut counting, *x0, *h0, *tol, config,
+                                                 &mut |record| pending.push_back(IterationRecord{mse: -record.mse, ..*record}));
+
+                *eval_count = counting.count;
+                *exhausted  = counting.exhausted;
+                result.map(|outcome| outcome.map(crate::negated_report))
+            },
+        };
+    }
+}
+
+// start the run if state is None, and otherwise search the next stencil size, passing every
+// record to keep. Returns the outcome of the run if it has ended
+fn advance_state<T, E>(state: &mut Option<FilterState<T>>, mse: &mut Counting<E>, x0: T, h0: T, tol: T,
+                       config: &FilterConfig, keep: &mut dyn FnMut(&IterationRecord<T>))
+    -> Option<Result<FilterReport<T>, OptimError>>
+    where T: Float, E: Objective<T>
+{
+    match state{
+        None        => match FilterState::new(mse, x0, h0, tol, config){
+                           Ok(new_state) => {*state = Some(new_state); None},
+                           Err(err)      => Some(Err(err)),
+                       },
+        Some(state) => state.step(mse, config, &mut |record| {keep(record); ControlFlow::Continue},
+                                  &mut Diagnostics::Log),
+    }
+}
+
+impl<T, F> Iterator for ImplicitFilterIter<T, F>
+    where T: Float, F: Objective<T>
+{
+    type Item = IterationRecord<T>;
+
+    fn next(&mut self) -> Option<Self::Item>{
+        while self.pending.is_empty() && self.outcome.is_none(){
+            self.advance();
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::implicit_filtering_traced;
+
+    #[test]
+    fn records_match_the_traced_history(){
+        let mse               = |x: f64, _h: f64| (x - 1.0).powi(2) + 0.1*(x - 1.0).powi(4);
+        let (result, history) = implicit_filtering_traced(mse, 3.0, 0.1, 1e-7);
+
+        let mut iter = ImplicitFilterIter::new(mse, 3.0, 0.1, 1e-7, &FilterConfig::default());
+        let records  = (&mut iter).collect::<Vec<_>>();
+
+        assert_eq!(records, history);
+        assert_eq!(iter.report().unwrap().as_ref().unwrap().result, result);
+        assert_eq!(iter.next(), None);
+    }
+}
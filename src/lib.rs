@@ -1,149 +1,3642 @@
 
+// Without the std feature the crate is no_std, and the scalar optimiser needs nothing beyond alloc
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::{String, ToString}, vec, vec::Vec};
+use core::fmt;
+use core::num::FpCategory;
+use core::ops::Range;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
+use std::time::Instant;
+#[cfg(not(feature = "std"))]
+use clock::Instant;
+
+use num_traits::Float;
+
+mod builder;
+pub use builder::ImplicitFilter;
+
+#[cfg(feature = "std")]
+pub mod euler;
+
+mod iter;
+pub use iter::ImplicitFilterIter;
+
+#[cfg(feature = "std")]
+pub mod nd;
+#[cfg(feature = "std")]
+pub use nd::{implicit_filtering_nd, implicit_filtering_nd_report, implicit_filtering_nd_with, FilterReportND, OptimResultND};
+#[cfg(all(feature = "std", feature = "ndarray"))]
+pub use nd::{implicit_filtering_ndarray, implicit_filtering_ndarray_with, OptimResultArray};
+
+mod objective;
+pub use objective::{ByRef, CountingObjective, Objective};
+#[cfg(feature = "std")]
+pub use objective::CachedObjective;
+use objective::{Averaged, ComplexStep, Counting, Negated};
+#[cfg(feature = "parallel")]
+use objective::Parallel;
+
+pub mod prelude;
+
+mod rng;
+use rng::SplitMix64;
+
+// Without the standard library there is no clock to read, so time_limit never ends a run and the
+// elapsed time is always reported as zero
+#[cfg(not(feature = "std"))]
+mod clock{
+    use core::time::Duration;
+
+    #[derive(Clone)]
+    #[derive(Copy)]
+    pub(crate) struct Instant;
+
+    impl Instant{
+        pub fn now() -> Instant{
+            Instant
+        }
+
+        pub fn elapsed(&self) -> Duration{
+            Duration::from_secs(0)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub mod testfuncs;
+
+pub use num_complex::Complex;
+
+// every entry point is generic over num_traits::Float, which TwoFloat implements
+#[cfg(feature = "twofloat")]
+pub use twofloat::TwoFloat;
+
 const LINE_SEARCH_REDUCTION: f64 = 0.7;
 const STENCIL_REDUCTION: f64 = 0.25;
 const ARMIJO_CONSTANT: f64 = 0.001;
-const MAX_ITERS: usize = 10;
+const MAX_INNER_ITERS: usize = 10;
+const MAX_LINE_SEARCH_ITERS: usize = 10;
+const MAX_OUTER_ITERS: usize = 20;
+const MAX_EXPANSION: f64 = 16.0;
+const MAX_STEP: f64 = 3.0;
+const HESS_FLOOR: f64 = 1e-8;
+const STALL_PATIENCE: usize = 3;
+const TABLE_PRECISION: usize = 10;
+// estimate_h0 tries stepsizes from max(|x0|, 1) down by factors of ten this many times, and 
+// accepts a stepsize once the gradient estimate agrees to within H0_AGREEMENT with the estimate at
+// the next smaller stepsize
+const H0_TRIALS: i32 = 8;
+// the smallest fraction of the previous trial step that the quadratic line search will try next
+const QUADRATIC_MIN_FRACTION: f64 = 0.1;
+const H0_AGREEMENT: f64 = 0.1;
+// the trust region shrinks to TRUST_SHRINK times the step whenever the actual decrease is less than
+// TRUST_POOR_FIT times the predicted one, and grows by TRUST_GROWTH when a step to its edge achieves
+// more than TRUST_GOOD_FIT times the prediction
+const TRUST_SHRINK: f64 = 0.25;
+const TRUST_GROWTH: f64 = 2.0;
+const TRUST_POOR_FIT: f64 = 0.25;
+const TRUST_GOOD_FIT: f64 = 0.75;
 
+#[derive(Debug)]
 #[derive(Clone)]
 #[derive(Copy)]
 #[derive(PartialEq)]
-pub struct OptimResult{
-   pub x: f64,
-   pub mse: f64,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OptimResult<T = f64>{
+   pub x: T,
+   pub mse: T,
 }
 
-fn report_stencil_failure( msg: &str){
-    eprintln!("\nStencil Failure: {}", msg); 
-}
+pub type OptimResultF64 = OptimResult<f64>;
 
-// estimate the gradient of the objective function
-fn generate_gradient(mse: fn(f64, f64) -> f64, result:  &OptimResult, h: f64) -> Option<(f64, f64)>{
+impl<T: Float> OptimResult<T>{
+    // true if self has a strictly lower mse than other. A NaN mse is worse than any other value, so
+    // a NaN result is never better than anything
+    pub fn better_than(&self, other: &OptimResult<T>) -> bool{
+        !self.mse.is_nan() && (other.mse.is_nan() || self.mse < other.mse)
+    }
 
-   let mse_centre   = result.mse;
-   let mse_right = mse(result.x + h,h);
-   let mse_left  = mse(result.x - h,h);
+    // how far the result lies from x, e.g. from a known minimum
+    pub fn distance_to(&self, x: T) -> T{
+        (self.x - x).abs()
+    }
 
+    // the central difference estimate of the second derivative of mse at the result, with 
+    // stepsize h, which shows how sharply the minimum is defined. The centre is evaluated afresh
+    // at h, since self.mse may have come from a different stencil size
+    pub fn curvature<F: Objective<T>>(&self, mut mse: F, h: T) -> T{
+        let mse_centre            = mse.mse(self.x, h);
+        let (mse_right, mse_left) = mse.mse_pair(self.x + h, self.x - h, h);
 
-   let grad = (mse_right - mse_left)/(2.0*h);
-   let hess = (mse_right + mse_left - 2.0*mse_centre)/(h*h);
+        (mse_right + mse_left - cast::<T>(2.0)*mse_centre)/(h*h)
+    }
 
-   // if the centre point is the smallest so that no descent direction can be identified, or if 
-   // the first derivative is estimated to be small relative to the stepsize, report an error
-   let no_descent_direction = mse_right >= mse_centre &&  mse_left >= mse_centre;
-   let grad_o_h = grad.abs() <= h;
+    // 1/sqrt(curvature), which is the standard error of x when mse is a negative log likelihood.
+    // For a sum of squared residuals with noise variance σ², multiply by σ√2. None unless the 
+    // curvature is positive and finite, as it is at a well defined minimum
+    pub fn approx_std_error<F: Objective<T>>(&self, mse: F, h: T) -> Option<T>{
+        let curvature = self.curvature(mse, h);
 
-   if no_descent_direction || grad_o_h{ 
-        None
-   } else {
-        Some((grad, hess))
-   }
+        if curvature.is_finite() && curvature > T::zero() {Some(curvature.sqrt().recip())} else {None}
+    }
 }
 
-// A backtracking line search that attempts to find a point that satisfies the 
-// Armijo Condition. Since only an approximate gradient is used, this search is not guaranteed to a
-// actually succeed
-fn backtracking_line_search(mse: fn(f64, f64) -> f64, x: f64, p: f64, grad: f64, h:f64) -> Option<OptimResult> 
+// results are approximately equal when both x and mse are, to the same tolerance
+#[cfg(feature = "approx")]
+impl<T: approx::AbsDiffEq> approx::AbsDiffEq for OptimResult<T>
+    where T::Epsilon: Copy
+{
+    type Epsilon = T::Epsilon;
+
+    fn default_epsilon() -> T::Epsilon{
+        T::default_epsilon()
+    }
+
+    fn abs_diff_eq(&self, other: &Self, epsilon: T::Epsilon) -> bool{
+        self.x.abs_diff_eq(&other.x, epsilon) && self.mse.abs_diff_eq(&other.mse, epsilon)
+    }
+}
+
+#[cfg(feature = "approx")]
+impl<T: approx::RelativeEq> approx::RelativeEq for OptimResult<T>
+    where T::Epsilon: Copy
+{
+    fn default_max_relative() -> T::Epsilon{
+        T::default_max_relative()
+    }
+
+    fn relative_eq(&self, other: &Self, epsilon: T::Epsilon, max_relative: T::Epsilon) -> bool{
+        self.x.relative_eq(&other.x, epsilon, max_relative) && self.mse.relative_eq(&other.mse, epsilon, max_relative)
+    }
+}
+
+// the result with the lowest mse, by better_than, keeping the earliest of any ties. None if 
+// results is empty
+pub fn min_by_mse<T, I>(results: I) -> Option<OptimResult<T>>
+    where T: Float, I: IntoIterator<Item = OptimResult<T>>
 {
-    let mse_old  = mse(x,h);
+    results.into_iter().fold(None, |best, result| match best{
+        Some(best_result) if !result.better_than(&best_result) => Some(best_result),
+        _                                                      => Some(result),
+    })
+}
+
+impl<T: Float> fmt::Display for OptimResult<T>{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "x = {:+.10}, mse = {:+.10}", show(self.x), show(self.mse))
+    }
+}
+
+// One inner iteration of the optimiser, i.e. one row of the diagnostic table
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IterationRecord<T = f64>{
+    // stencil size
+    pub h: T,
+    pub x: T,
+    pub mse: T,
+    // magnitude of the estimated gradient, or None if the stencil failed
+    pub grad_norm: Option<T>,
+    // whether the line search from this point found an acceptable step
+    pub line_search_ok: bool,
+}
+
+// Returned by an iteration callback to say whether the optimiser should keep going
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum ControlFlow{
+    Continue,
+    Stop,
+}
+
+// Why a successful run of the optimiser stopped
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConvergenceReason{
+    // reducing the stencil size moved x by no more than tol
+    ToleranceReached,
+    // the relative improvement in the objective between stencil sizes was no more than f_tol
+    ObjectiveTolerance,
+    // the estimated gradient fell below grad_tol
+    GradientBelowTolerance,
+    // every stencil size was tried without meeting any tolerance
+    MaxOuterIterations,
+    // as MaxOuterIterations, but the smallest stencil was unable to estimate a gradient
+    StencilFailure,
+    // as MaxOuterIterations, but the line search at the smallest stencil made no progress
+    LineSearchFailure,
+    // the iteration callback returned ControlFlow::Stop
+    StoppedByCallback,
+    // the run took longer than the configured time limit
+    TimeLimit,
+    // more than stall_patience stencil sizes in a row improved the objective by less than stall_eps
+    Stalled,
+    // the next stencil size in the schedule was smaller than min_h
+    StencilUnderflow,
+    // the next evaluation of the objective would have exceeded max_evals
+    EvalBudgetExhausted,
+    // no stencil size improved on the starting point, and the result was found by the fallback search
+    Fallback,
+}
+
+impl ConvergenceReason{
+    // true for the reasons that mean one of the convergence tests was met
+    fn tolerance_met(&self) -> bool{
+        matches!(self, ConvergenceReason::ToleranceReached 
+                     | ConvergenceReason::ObjectiveTolerance 
+                     | ConvergenceReason::GradientBelowTolerance)
+    }
+}
+
+// The result of a run together with how it ended
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FilterReport<T = f64>{
+    pub result: OptimResult<T>,
+    pub reason: ConvergenceReason,
+    // the number of times the objective was called, including the starting point, every stencil
+    // point and every line search trial
+    pub eval_count: usize,
+    // wall-clock time taken by the run
+    pub elapsed: Duration,
+    // the number of stencil sizes that were searched
+    pub outer_iters: usize,
+    // the gradient and Hessian at result.x. If the search didn't end with a successful estimate 
+    // there, they are differenced over the latest stencil size, with x ± h or, on a bound, a one 
+    // sided stencil, which costs two more evaluations. They are None if the stencil doesn't fit in
+    // the bounds or the objective isn't finite on it, and the Hessian is None if the gradient 
+    // scheme doesn't estimate one
+    pub final_grad: Option<T>,
+    pub final_hess: Option<T>,
+    // the work the objective reported doing during the run, from Objective::work, if it keeps count
+    pub objective_work: Option<usize>,
+}
+
+impl<T> FilterReport<T>{
+    // true if the run stopped because one of its convergence tests was met. A run that stopped
+    // for any other reason, such as running out of stencil sizes, evaluations or time, may still 
+    // have a good result, but nothing shows that it is close to a minimum
+    pub fn converged(&self) -> bool{
+        self.reason.tolerance_met()
+    }
+
+    // the number of stencil sizes that were searched
+    pub fn outer_iterations_used(&self) -> usize{
+        self.outer_iters
+    }
+}
+
+// The state of a run after the search at one stencil size, as handed to the checkpoint of
+// implicit_filtering_checkpointed. resume_from carries on from it exactly as the run would have, 
+// so with the serde feature a long run can be saved after each stencil size and picked up again
+// after an interruption. Only the wall-clock time starts afresh
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OuterLevelState<T = f64>{
+    // the stencil size that was just searched
+    pub h: T,
+    // the best point found so far
+    pub result: OptimResult<T>,
+    // the position of h in the schedule
+    pub outer_index: usize,
+    // the rest of the state of the outer loop
+    h0             : T,
+    outer_iters    : usize,
+    improved       : bool,
+    gradient_found : bool,
+    reason         : ConvergenceReason,
+    stalled_levels : usize,
+    derivatives    : Option<(T, Option<T>)>,
+    derivatives_x  : T,
+    eval_count     : usize,
+    objective_work : Option<usize>,
+}
+
+impl<T: Float> OuterLevelState<T>{
+    // the state in terms of the negated objective, for runs that maximise
+    fn negated(&self) -> OuterLevelState<T>{
+        OuterLevelState{result:      OptimResult{x: self.result.x, mse: -self.result.mse},
+                        derivatives: self.derivatives.map(|(g, h)| (-g, h.map(|h| -h))),
+                        ..*self}
+    }
+}
+
+// The ways in which a run of the optimiser can fail to produce a result
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum OptimError{
+    // no stencil level was able to identify a descent direction
+    StencilFailedEverywhere,
+    // the objective returned NaN or an infinite value at the starting point, or at the centre of a
+    // stencil
+    NonFiniteObjective,
+    // gradients were available, but no line search ever improved on the starting point
+    NoImprovement,
+    // a FilterConfig parameter is out of range
+    InvalidConfig(&'static str),
+    // a required ImplicitFilter parameter was never set
+    MissingParameter(&'static str),
+}
+
+impl fmt::Display for OptimError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        let msg = match self{
+            OptimError::StencilFailedEverywhere => "the stencil failed at every stepsize",
+            OptimError::NonFiniteObjective      => "the objective is not finite at the centre of the stencil",
+            OptimError::NoImprovement           => "no step improved on the starting point",
+            OptimError::InvalidConfig(msg)      => return write!(f, "invalid configuration: {}", msg),
+            OptimError::MissingParameter(name)  => return write!(f, "required parameter {} was not set", name),
+        };
 
-    for i in 0..MAX_ITERS{
+        write!(f, "{}", msg)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OptimError{}
+
+// How generate_gradient estimates the derivatives of the objective
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum GradientScheme{
+    // (f(x+h) - f(x-h))/2h, with the Hessian from the same three points
+    Central,
+    // (f(x+h) - f(x))/h. This saves one evaluation per gradient, but gives no curvature 
+    // information, so the search direction is steepest descent rather than quasi-Newton
+    Forward,
+    // the five point stencil x, x ± h, x ± 2h, giving fourth order accurate derivatives for two
+    // more evaluations per gradient than Central
+    CentralFourthOrder,
+    // Im(f(x+ih))/h. There is no subtractive cancellation, so the gradient is accurate to 
+    // round-off even for tiny h, and the Hessian comes from the Central stencil. This needs an
+    // objective that accepts complex x, so it is only available through implicit_filtering_complex
+    ComplexStep,
+    // Richardson extrapolation (4*D(h/2) - D(h))/3 of the central differences D at h and h/2, 
+    // which cancels their leading error term. The Hessian comes from the points at x ± h
+    Richardson,
+}
 
-        let a = LINE_SEARCH_REDUCTION.powi(i as i32); 
+// What the multidimensional optimiser does with the stencil around x
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum StencilKind{
+    // only use the stencil to estimate the gradient and Hessian for a quasi-Newton step
+    FiniteDifference,
+    // as FiniteDifference, but when the quasi-Newton step fails, move to the best of the points 
+    // x ± h*e_i instead if it improves on x. This is the classic compass search, which can keep
+    // making progress on non-smooth objectives
+    Compass,
+}
+
+// How grad_search chooses the step along the search direction
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum LineSearch{
+    // backtracking until the Armijo sufficient decrease condition holds
+    Armijo,
+    // a bracketing search for a step satisfying the weak Wolfe conditions: sufficient decrease 
+    // with constant c1, and the curvature condition with constant c2, where 0 < c1 < c2 < 1. The 
+    // curvature condition needs the gradient at the trial point, which costs two extra objective 
+    // evaluations each time a trial passes the decrease test
+    Wolfe{c1: f64, c2: f64},
+    // halving the step from the full quasi-Newton step until the Armijo condition holds, for up to
+    // max_iters trials. The step shrinks faster than with Armijo's line_search_reduction, which 
+    // helps when the search direction is badly scaled
+    Bisection{max_iters: usize},
+    // backtracking in which each trial step is the minimiser of the quadratic that matches the
+    // objective and its directional derivative at the start point and the objective at the last 
+    // rejected trial. When that minimiser is out of range, the step shrinks by 
+    // line_search_reduction as in Armijo
+    QuadraticInterp,
+    // Armijo backtracking in which the decrease is measured from the largest objective value among
+    // the last memory points of the search at the current stencil size (Grippo, Lampariello and 
+    // Lucidi), rather than from the current point. Steps that briefly go uphill are accepted, 
+    // which lets the search cross a shallow ridge that would stall the monotone search. With a 
+    // memory of 1 this is Armijo
+    NonMonotone{memory: usize},
+}
 
-        let x_new            = x + a*p;
-        let mse_new          = mse(x_new, h);
+// Whether the optimiser looks for a minimum or a maximum of the objective. A maximum is found by
+// minimising the negated objective, but the results and callback records hold the objective's 
+// own values. The diagnostic table shows the negated values that are actually minimised
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum OptimDirection{
+    Minimize,
+    Maximize,
+}
 
-        let required_decrease =  ARMIJO_CONSTANT*a*p*grad;
-        let actual_decrease = mse_new - mse_old;
+// How the change in x between stencil sizes is compared with tol
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum ToleranceKind{
+    // stop once |change| <= tol
+    Absolute,
+    // stop once |change| <= tol*max(|x|, min_scale), where x is the point before the change. 
+    // min_scale stands in for |x| near zero, where a relative change means nothing
+    Relative{min_scale: f64},
+}
 
-        if actual_decrease <= required_decrease{
-            return Some(OptimResult{x: x_new, mse: mse_new}) 
+impl ToleranceKind{
+    // the largest change in a point of size x_size that meets tol
+    fn threshold<T: Float>(&self, tol: T, x_size: T) -> T{
+        match self{
+            ToleranceKind::Absolute             => tol,
+            ToleranceKind::Relative{min_scale} => tol*x_size.max(cast(*min_scale)),
         }
     }
+}
 
-    None
+// How grad_search decides how far to move along the search direction
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum StepStrategy{
+    // search along the direction as config.line_search says
+    LineSearch,
+    // take the quasi-Newton step, cut down to a trust radius, and accept it if the objective 
+    // decreases by at least armijo_constant times the decrease predicted by the quadratic model 
+    // from the stencil. The radius starts at init_radius for each stencil size, shrinks after a 
+    // poorly predicted step and grows after a well predicted one, so a model that fits well 
+    // costs a single evaluation per step
+    TrustRegion{init_radius: f64},
 }
 
-// A line search algorithm that approximately computes the gradient and Hessian using
-// finite differences
-fn grad_search(mse: fn(f64, f64) -> f64, x: f64, h: f64) -> Option<OptimResult>{
+// What to do when the search at every stencil size fails to improve on the starting point
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum Fallback{
+    // report the failure
+    None,
+    // a golden section search for a minimum on [a, b], evaluating the objective at the last 
+    // stencil size tried and stopping once the bracket is no wider than it. This needs no 
+    // derivatives, so it still works when the objective is too noisy or too flat for any stencil
+    // to find a gradient, but it assumes there is a single minimum on the bracket
+    GoldenSection{a: f64, b: f64},
+}
 
-    let old_result = OptimResult{ x, mse: mse(x,h)};
+// The sequence of stencil sizes used by the outer loop of the algorithm
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum StencilSchedule{
+    // h0*ratio^i, for max_outer_iters levels
+    Geometric{ratio: f64},
+    // exactly these stencil sizes, in order. h0 is then only used to evaluate the starting point
+    Custom(Vec<f64>),
+    // h0/(i + 1), for max_outer_iters levels
+    Harmonic,
+}
 
-    let mut current_result = old_result;
+impl StencilSchedule{
+    // the number of stencil sizes to visit
+    fn len(&self, max_outer_iters: usize) -> usize{
+        match self{
+            StencilSchedule::Custom(sizes) => sizes.len(),
+            _                              => max_outer_iters,
+        }
+    }
 
-    eprintln!("\nCommencing optimisation routine:\n   h = {0: <12}\n   β = {1: <12}\n", h, x);
+    // the stencil size for outer iteration i
+    fn stencil_size<T: Float>(&self, h0: T, i: usize) -> T{
+        match self{
+            StencilSchedule::Geometric{ratio} => h0*power(cast(*ratio), i),
+            StencilSchedule::Custom(sizes)    => cast(sizes[i]),
+            StencilSchedule::Harmonic         => h0/cast(i as f64 + 1.0),
+        }
+    }
+}
 
-    eprintln!("{0: ^+013.10}|{1: ^018.10}|{2: ^019.10}|", "   β", "MSE", "‖∇ₕMSE‖");
-    eprintln!("==============================================================");
-    
-    for _i in 0..MAX_ITERS{
+// The tunable constants of the algorithm. FilterConfig::default() reproduces the values used by
+// implicit_filtering
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct FilterConfig{
+    // factor by which the line search step shrinks after each failed trial
+    pub line_search_reduction: f64,
+    // fraction of the predicted decrease that a line search step must achieve
+    pub armijo_constant: f64,
+    // number of inner iterations per stencil size
+    pub max_inner_iters: usize,
+    // number of trials per line search, other than the bisection search, which sets its own
+    pub max_line_search_iters: usize,
+    // number of stencil sizes to try, unless the schedule lists its own
+    pub max_outer_iters: usize,
+    // how the stencil size shrinks between outer iterations
+    pub schedule: StencilSchedule,
+    // the box [lower, upper] that x is confined to. The objective is never evaluated outside it
+    pub lower: f64,
+    pub upper: f64,
+    pub gradient_scheme: GradientScheme,
+    // when the central difference is too small to trust but exactly one side of the stencil 
+    // improves on the centre, use a one-sided difference towards that side instead of declaring a
+    // stencil failure. Near a kink the central difference averages two unrelated slopes
+    pub allow_one_sided: bool,
+    // the left and right offsets of the three point central stencil as multiples of h, so that it
+    // samples x - left*h, x and x + right*h, or None for x ± h. An uneven stencil can keep clear of
+    // a bound or a singularity on one side. The other gradient schemes always use x ± h
+    pub stencil_offsets: Option<(f64, f64)>,
+    // the number of evaluations averaged at each point of the stencil, including the centre, when
+    // estimating the gradient. More than one only helps if the objective is noisy, returning a 
+    // different value each time it is called at the same point
+    pub stencil_samples: usize,
+    pub line_search: LineSearch,
+    // only used by the scalar optimiser
+    pub step_strategy: StepStrategy,
+    // the shortest step, as a fraction of the full search direction, that the line searches based 
+    // on the Armijo condition will try. Once the trials fall below it the line search fails, 
+    // rather than accepting a step too small to be worth an iteration. Zero disables the floor
+    pub min_step_frac: f64,
+    // only used by the multidimensional optimiser
+    pub stencil_kind: StencilKind,
+    pub direction: OptimDirection,
+    // the stencil is considered to have failed when |gradient| <= grad_h_ratio*h
+    pub grad_h_ratio: f64,
+    // Hessian estimates no larger than this are treated as unreliable, and grad_search falls back
+    // to steepest descent rather than dividing by them
+    pub hess_floor: f64,
+    // the longest search direction grad_search will take
+    pub max_step: f64,
+    // let the Armijo line search grow the step beyond the quasi-Newton step when that keeps 
+    // improving the objective
+    pub allow_expansion: bool,
+    // when a stencil gives no Hessian estimate that can be trusted, take the quasi-Newton step with
+    // the last one that could be, carried over from earlier stencil sizes, rather than steepest 
+    // descent. For a smooth objective the curvature changes slowly as h shrinks, so a step scaled
+    // by the older estimate is usually closer to the right length than an unscaled one
+    pub seed_hessian: bool,
+    pub fallback: Fallback,
+    // the inner search stops, as converged, once the estimated gradient is smaller than this
+    pub grad_tol: f64,
+    // whether tol is an absolute or relative bound on the change in x between stencil sizes
+    pub tol_kind: ToleranceKind,
+    // the run stops once the relative improvement in the objective between stencil sizes is no 
+    // more than this. Zero disables the test
+    pub f_tol: f64,
+    // the run stops once more than stall_patience consecutive stencil sizes have each improved the
+    // objective by less than stall_eps. Zero disables the test
+    pub stall_eps: f64,
+    pub stall_patience: usize,
+    // log the diagnostic table and failure messages
+    pub verbose: bool,
+    // the number of decimal places shown in the diagnostic table
+    pub precision: usize,
+    // stop before starting a new stencil size once this much time has passed, returning the best 
+    // result so far
+    pub time_limit: Option<Duration>,
+    // the smallest stencil size the schedule may reach. A custom schedule may not list any smaller.
+    // None gives the square root of the epsilon of the float type the run works in
+    pub min_h: Option<f64>,
+    // the most times the objective may be called, counting as eval_count does. The run stops with
+    // the best result so far as soon as another call would exceed it, even part way through a 
+    // stencil or line search
+    pub max_evals: Option<usize>,
+    // the index in the schedule of the first stencil size to search. Skipping the coarse sizes is
+    // useful when warm starting from a point that is already close to a minimum
+    pub start_level: usize,
+}
 
-        // attempt to compute approximate gradient and Hessian
-        let (grad, hess) = match generate_gradient(mse, &current_result, h){
-                       Some(gh)   => gh,
-                       None       => { eprintln!("{0: ^+013.10}|{1: ^018.10}|{2: ^019.10}|", 
-                                                    current_result.x, current_result.mse, "N/A");
-                                       report_stencil_failure("Unable to clearly estimate gradient");
-                                       break},
-                };
-                        
-        // compute quasi-Newton search direction          
-        let p  = -grad.signum()*grad.abs()/hess;
+impl Default for FilterConfig{
+    fn default() -> FilterConfig{
+        FilterConfig{line_search_reduction: LINE_SEARCH_REDUCTION,
+                     armijo_constant:       ARMIJO_CONSTANT,
+                     max_inner_iters:       MAX_INNER_ITERS,
+                     max_line_search_iters: MAX_LINE_SEARCH_ITERS,
+                     max_outer_iters:       MAX_OUTER_ITERS,
+                     schedule:              StencilSchedule::Geometric{ratio: STENCIL_REDUCTION},
+                     lower:                 f64::NEG_INFINITY,
+                     upper:                 f64::INFINITY,
+                     gradient_scheme:       GradientScheme::Central,
+                     allow_one_sided:       false,
+                     stencil_offsets:       None,
+                     stencil_samples:       1,
+                     line_search:           LineSearch::Armijo,
+                     step_strategy:         StepStrategy::LineSearch,
+                     min_step_frac:         0.0,
+                     stencil_kind:          StencilKind::FiniteDifference,
+                     direction:             OptimDirection::Minimize,
+                     grad_h_ratio:          1.0,
+                     hess_floor:            HESS_FLOOR,
+                     max_step:              MAX_STEP,
+                     allow_expansion:       false,
+                     seed_hessian:          false,
+                     fallback:              Fallback::None,
+                     grad_tol:              0.0,
+                     tol_kind:              ToleranceKind::Absolute,
+                     f_tol:                 0.0,
+                     stall_eps:             0.0,
+                     stall_patience:        STALL_PATIENCE,
+                     verbose:               true,
+                     precision:             TABLE_PRECISION,
+                     time_limit:            None,
+                     min_h:                 None,
+                     max_evals:             None,
+                     start_level:           0}
+    }
+}
 
-        let p = if p*grad <= 0.0 {p} else {-grad.signum()*grad.abs()}; // check that a descent direction is defined
-        let p = if p.abs() <= 3.0 {p} else {-grad.signum()*3.0};       // check the search direction isn't too big
+impl FilterConfig{
+    // max_iters bounds both the inner iterations and the line search trials
+    pub fn new(line_search_reduction: f64, stencil_reduction: f64, armijo_constant: f64,
+               max_iters: usize, max_outer_iters: usize) -> Result<FilterConfig, OptimError>{
 
-  
+        let config = FilterConfig{line_search_reduction, armijo_constant, max_outer_iters, 
+                                  max_inner_iters:       max_iters,
+                                  max_line_search_iters: max_iters,
+                                  schedule: StencilSchedule::Geometric{ratio: stencil_reduction},
+                                  ..FilterConfig::default()};
+        config.validate()?;
 
-        // print table row
-        eprintln!("{0: ^+013.10}|{1: ^018.10}|{2: ^019.10}|", current_result.x, current_result.mse, grad.abs());
+        Ok(config)
+    }
 
-        assert!(p*grad <= 0.0); // this should always be true, but check anyway just in case
+    // the same as the default configuration, but with x confined to [lower, upper]
+    pub fn bounded(lower: f64, upper: f64) -> Result<FilterConfig, OptimError>{
+        let config = FilterConfig{lower, upper, ..FilterConfig::default()};
+        config.validate()?;
 
-        // conduct a backtracking line search
-        match backtracking_line_search(mse, current_result.x, p, grad, h){
-            Some(result) => current_result = result,
-            None         => {report_stencil_failure("Line Search Failure");
-                             break;},
-        };
+        Ok(config)
+    }
 
+    // the smallest stencil size in the working precision
+    fn min_h<T: Float>(&self) -> T{
+        self.min_h.map_or_else(|| T::epsilon().sqrt(), cast)
     }
 
-    if current_result == old_result || current_result.mse >= old_result.mse{
-        None
-    } else {
-        Some(current_result)
+    // an infinite bound is skipped rather than compared, since not every Float type orders its
+    // infinities the way f64 does (TwoFloat reports -inf <= x as false)
+    fn feasible<T: Float>(&self, x: T) -> bool{
+        !x.is_nan()
+            && (self.lower == f64::NEG_INFINITY || cast::<T>(self.lower) <= x)
+            && (self.upper == f64::INFINITY     || x <= cast(self.upper))
+    }
+
+    // check that every parameter lies in the range the algorithm relies on
+    pub fn validate(&self) -> Result<(), OptimError>{
+        let in_unit_interval = |v: f64| v > 0.0 && v < 1.0;
+
+        if !in_unit_interval(self.line_search_reduction){
+            return Err(OptimError::InvalidConfig("line_search_reduction must lie in (0,1)"));
+        }
+        match &self.schedule{
+            StencilSchedule::Geometric{ratio} if !in_unit_interval(*ratio) => {
+                return Err(OptimError::InvalidConfig("stencil_reduction must lie in (0,1)"));
+            },
+            StencilSchedule::Custom(sizes) if sizes.iter().any(|&h| !(h.is_finite() && h > 0.0)) => {
+                return Err(OptimError::InvalidConfig("custom stencil sizes must be positive and finite"));
+            },
+            _ => (),
+        }
+        if self.grad_h_ratio.is_nan() || self.grad_h_ratio < 0.0{
+            return Err(OptimError::InvalidConfig("grad_h_ratio must be non-negative"));
+        }
+        if self.max_step.is_nan() || self.max_step <= 0.0{
+            return Err(OptimError::InvalidConfig("max_step must be positive"));
+        }
+        match self.line_search{
+            LineSearch::Wolfe{c1, c2} if !(0.0 < c1 && c1 < c2 && c2 < 1.0) => {
+                return Err(OptimError::InvalidConfig("Wolfe constants must satisfy 0 < c1 < c2 < 1"));
+            },
+            LineSearch::Bisection{max_iters: 0} => {
+                return Err(OptimError::InvalidConfig("the bisection search needs at least one trial"));
+            },
+            LineSearch::NonMonotone{memory: 0} => {
+                return Err(OptimError::InvalidConfig("the non-monotone search needs a memory of at least one"));
+            },
+            _ => (),
+        }
+        if let Some((left, right)) = self.stencil_offsets{
+            if !(left.is_finite() && right.is_finite() && left > 0.0 && right > 0.0){
+                return Err(OptimError::InvalidConfig("stencil offsets must be positive and finite"));
+            }
+        }
+        if let StepStrategy::TrustRegion{init_radius} = self.step_strategy{
+            if init_radius.is_nan() || init_radius <= 0.0{
+                return Err(OptimError::InvalidConfig("init_radius must be positive"));
+            }
+        }
+        if let Fallback::GoldenSection{a, b} = self.fallback{
+            if !(a.is_finite() && b.is_finite() && a < b){
+                return Err(OptimError::InvalidConfig("the golden section bracket must be finite, with a < b"));
+            }
+        }
+        if self.min_step_frac.is_nan() || self.min_step_frac < 0.0 || self.min_step_frac > 1.0{
+            return Err(OptimError::InvalidConfig("min_step_frac must lie in [0,1]"));
+        }
+        if let ToleranceKind::Relative{min_scale} = self.tol_kind{
+            if min_scale.is_nan() || min_scale < 0.0{
+                return Err(OptimError::InvalidConfig("min_scale must be non-negative"));
+            }
+        }
+        if self.lower.is_nan() || self.upper.is_nan() || self.lower >= self.upper{
+            return Err(OptimError::InvalidConfig("lower must be less than upper"));
+        }
+        if let Some(min_h) = self.min_h{
+            if min_h.is_nan() || min_h <= 0.0{
+                return Err(OptimError::InvalidConfig("min_h must be positive"));
+            }
+        }
+        // the run would end at the first size below min_h, so the sizes after it would never be 
+        // visited. Without a float type the default can only be checked in double precision, and 
+        // FilterState::new checks it again in the working precision
+        if let StencilSchedule::Custom(sizes) = &self.schedule{
+            if sizes.iter().any(|&size| size.is_nan() || size < self.min_h::<f64>()){
+                return Err(OptimError::InvalidConfig("custom stencil sizes must be no smaller than min_h"));
+            }
+        }
+        if self.stencil_samples == 0{
+            return Err(OptimError::InvalidConfig("stencil_samples must be at least 1"));
+        }
+        if self.max_evals == Some(0){
+            return Err(OptimError::InvalidConfig("max_evals must allow the starting point to be evaluated"));
+        }
+        if self.max_outer_iters == 0{
+            return Err(OptimError::InvalidConfig("max_outer_iters must be at least 1"));
+        }
+        if self.start_level >= self.schedule.len(self.max_outer_iters){
+            return Err(OptimError::InvalidConfig("start_level must be less than the number of stencil sizes"));
+        }
+
+        Ok(())
     }
 }
 
-pub fn implicit_filtering(mse: fn(f64, f64) -> f64, x0: f64, h0: f64, tol: f64) -> OptimResult{
+// why a single call to grad_search was unable to improve on its starting point
+enum SearchFailure{
+    Stencil,
+    NoImprovement,
+    // the objective was not finite at the centre of the stencil
+    NonFinite,
+}
 
-    let mut old_result = OptimResult{x: x0, mse: mse(x0,h0)};
+// everything the outer loop needs to know about a call to grad_search
+struct SearchOutcome<T>{
+    result: Result<OptimResult<T>, SearchFailure>,
+    // the callback asked for the run to end
+    stopped: bool,
+    // the gradient fell below grad_tol, so the result is converged even if x never moved
+    grad_converged: bool,
+    // the last gradient and Hessian estimated successfully, and the point they were estimated at
+    derivatives: Option<(T, Option<T>)>,
+    derivatives_x: T,
+}
 
-    for i in 0..20{
-        let h :f64 = h0*STENCIL_REDUCTION.powi(i as i32);
-        
-        let grad_result =  grad_search(mse, old_result.x, h);
+// convert an f64 constant to the working precision, saturating to ±∞ if it's out of range
+fn cast<T: Float>(v: f64) -> T{
+    T::from(v).unwrap_or_else(|| if v > 0.0 {T::infinity()} else {T::neg_infinity()})
+}
 
-        let new_result = match grad_result{
-                           Some(result) => result,
-                           None         => continue
-                        };
+// base^i. An index too large for powi saturates rather than wrapping round to a negative power. 
+// The result can still underflow to zero or overflow, which the callers check for
+fn power<T: Float>(base: T, i: usize) -> T{
+    base.powi(i.min(i32::MAX as usize) as i32)
+}
+
+// convert back to f64 for printing
+fn show<T: Float>(v: T) -> f64{
+    v.to_f64().unwrap_or(f64::NAN)
+}
+
+// Diagnostics go through the log crate: failures as warnings, and the table as debug records, one 
+// per row, with the heading as a single multi-line record
+fn report_stencil_failure(config: &FilterConfig, out: &mut Diagnostics, msg: &str){
+    if config.verbose{
+        out.log(log::Level::Warn, format_args!("Stencil Failure: {}", msg)); 
+    }
+}
 
-        let diff = (old_result.x - new_result.x).abs();
+// where the diagnostic table and failure messages are sent
+pub(crate) enum Diagnostics<'a>{
+    // the log crate, with the table at debug level and failures at warn level
+    Log,
+    // a user supplied sink, laid out as the demo binary lays out the log. Write errors are ignored,
+    // since failing to show the diagnostics is no reason to abandon the optimisation
+    Writer(&'a mut dyn fmt::Write),
+}
 
-        old_result = new_result;
+// an io::Write seen as a fmt::Write, for implicit_filtering_to
+#[cfg(feature = "std")]
+struct IoWriter<'a>(&'a mut dyn Write);
 
-        // terminate when reducing the stepsize makes no difference
-        if diff <= tol {
-            break;
+#[cfg(feature = "std")]
+impl fmt::Write for IoWriter<'_>{
+    fn write_str(&mut self, s: &str) -> fmt::Result{
+        self.0.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl Diagnostics<'_>{
+    fn log(&mut self, level: log::Level, args: fmt::Arguments){
+        match self{
+            Diagnostics::Log       => log::log!(level, "{}", args),
+            Diagnostics::Writer(w) => {
+                let msg = args.to_string();
+
+                let _ = if level <= log::Level::Warn || msg.contains('\n'){
+                            writeln!(w, "\n{}", msg)
+                        } else {
+                            writeln!(w, "{}", msg)
+                        };
+            },
         }
     }
+}
+
+// log the heading of the diagnostic table at the start of a new stencil size
+fn print_table_header<T: Float>(config: &FilterConfig, out: &mut Diagnostics, h: T, x: T){
+    if !config.verbose{
+        return;
+    }
+
+    let (x_width, mse_width, grad_width) = table_widths(config);
+
+    out.log(log::Level::Debug, 
+            format_args!("Commencing optimisation routine:\n   h = {0: <12}\n   β = {1: <12}\n\n{2: ^xw$}|{3: ^mw$}|{4: ^gw$}|\n{5}", 
+                         show(h), show(x), "   β", "MSE", "‖∇ₕMSE‖", "=".repeat(x_width + mse_width + grad_width + 12),
+                         xw = x_width, mw = mse_width, gw = grad_width));
+}
+
+// the widths of the β, MSE and gradient columns of the diagnostic table. Besides the decimal 
+// places, β has room for a sign and one digit, and the MSE and gradient for seven and eight digits
+fn table_widths(config: &FilterConfig) -> (usize, usize, usize){
+    (config.precision + 3, config.precision + 8, config.precision + 9)
+}
+
+// log one row of the diagnostic table, showing N/A if there is no gradient estimate
+fn print_table_row<T: Float>(config: &FilterConfig, out: &mut Diagnostics, result: &OptimResult<T>, grad_norm: Option<T>){
+    if !config.verbose{
+        return;
+    }
+
+    let (xw, mw, gw) = table_widths(config);
+    let p            = config.precision;
+
+    let x   = table_cell(show(result.x), xw, p, true);
+    let mse = table_cell(show(result.mse), mw, p, false);
+
+    match grad_norm{
+        Some(g) => out.log(log::Level::Debug, format_args!("{}|{}|{}|", x, mse, table_cell(show(g), gw, p, false))),
+        None    => out.log(log::Level::Debug, format_args!("{}|{}|{: ^gw$}|", x, mse, "N/A", gw = gw)),
+    }
+}
+
+// one number of the diagnostic table, zero padded to width, with a sign if signed. NaN and the 
+// infinities are shown as a row of dashes instead, since zero padding would put digits in front 
+// of them and the column would no longer read as a number
+fn table_cell(v: f64, width: usize, precision: usize, signed: bool) -> String{
+    if !v.is_finite(){
+        format!("{: ^w$}", "-".repeat(precision), w = width)
+    } else if signed {
+        format!("{: ^+0w$.p$}", v, w = width, p = precision)
+    } else {
+        format!("{: ^0w$.p$}", v, w = width, p = precision)
+    }
+}
+
+// true if none of the sampled objective values is NaN or infinite. A stencil containing such a 
+// value can't be differenced, so it is treated as a stencil failure
+fn all_finite<T: Float>(values: &[T]) -> bool{
+    values.iter().all(|v| v.is_finite())
+}
+
+// estimate the gradient of the objective function, along with the Hessian if the scheme provides
+// one, averaging config.stencil_samples evaluations at each stencil point. steps holds the 
+// distances (left, right) from x to the points of an uneven central stencil, or None for x ± h
+fn generate_gradient<T, E>(mse: &mut E, result:  &OptimResult<T>, h: T, steps: Option<(T, T)>, config: &FilterConfig) 
+    -> Option<(T, Option<T>)>
+    where T: Float, E: Objective<T>
+{
+    if config.stencil_samples == 1{
+        return stencil_gradient(mse, result, h, steps, config);
+    }
+
+    // the centre is resampled too, so that it is as accurate as the points it is compared with
+    let mut averaged = Averaged{inner: mse, samples: config.stencil_samples};
+    let centre       = OptimResult{x: result.x, mse: averaged.mse(result.x, h)};
+
+    if !centre.mse.is_finite(){
+        return None;
+    }
 
-    old_result
+    stencil_gradient(&mut averaged, &centre, h, steps, config)
 }
 
+// generate_gradient from a single evaluation at each stencil point. When one side of the stencil
+// falls outside the bounds, a one-sided difference using x, x ± h and x ± 2h on the feasible side
+// is used instead
+fn stencil_gradient<T, E>(mse: &mut E, result:  &OptimResult<T>, h: T, steps: Option<(T, T)>, config: &FilterConfig) 
+    -> Option<(T, Option<T>)>
+    where T: Float, E: Objective<T>
+{
+
+   let mse_centre   = result.mse;
+
+   // the steps only apply to the three point central stencil
+   let (h_left, h_right) = match config.gradient_scheme{
+                               GradientScheme::Central => steps.unwrap_or((h, h)),
+                               _                       => (h, h),
+                           };
+
+   let right_feasible = config.feasible(result.x + h_right);
+   let left_feasible  = config.feasible(result.x - h_left);
+
+   if let Some(grad) = mse.grad(result.x, h){
+       return analytic_gradient(mse, result, h, config, grad, right_feasible && left_feasible);
+   }
+
+   if config.gradient_scheme == GradientScheme::Forward{
+       return forward_gradient(mse, result, h, config, right_feasible);
+   }
+
+   if !(right_feasible && left_feasible){
+       return one_sided_gradient(mse, result, h, config, right_feasible);
+   }
+
+   // fall back to the three point stencil if the wider one doesn't fit in the bounds
+   if config.gradient_scheme == GradientScheme::CentralFourthOrder
+       && config.feasible(result.x + cast::<T>(2.0)*h) && config.feasible(result.x - cast::<T>(2.0)*h){
+       return fourth_order_gradient(mse, result, h, config);
+   }
+
+   if config.gradient_scheme == GradientScheme::Richardson{
+       return richardson_gradient(mse, result, h, config);
+   }
+
+   let (mse_right, mse_left) = mse.mse_pair(result.x + h_right, result.x - h_left, h);
+
+   if !all_finite(&[mse_right, mse_left]){
+       return None;
+   }
+
+   let two  = cast::<T>(2.0);
+
+   // the derivatives of the quadratic through the three points, which for an uneven stencil is 
+   // only first order accurate for the Hessian
+   let (grad, hess) = if h_left == h_right && h_left == h{
+                          ((mse_right - mse_left)/(two*h), (mse_right + mse_left - two*mse_centre)/(h*h))
+                      } else {
+                          let (l, r)  = (h_left, h_right);
+                          let spacing = l*r*(l + r);
+
+                          ((l*l*mse_right - r*r*mse_left - (l*l - r*r)*mse_centre)/spacing,
+                           two*(l*mse_right + r*mse_left - (l + r)*mse_centre)/spacing)
+                      };
+
+   // if the centre point is the smallest so that no descent direction can be identified, or if 
+   // the first derivative is estimated to be small relative to the stepsize (scaled by 
+   // grad_h_ratio), report an error
+   let no_descent_direction = mse_right >= mse_centre &&  mse_left >= mse_centre;
+   let grad_o_h = grad.abs() <= cast::<T>(config.grad_h_ratio)*h;
+
+   let right_better = mse_right < mse_centre;
+   let left_better  = mse_left < mse_centre;
+
+   if grad_o_h && config.allow_one_sided && right_better != left_better{
+        let grad = if right_better {(mse_right - mse_centre)/h_right} else {(mse_centre - mse_left)/h_left};
+
+        return if grad.abs() <= cast::<T>(config.grad_h_ratio)*h {None} else {Some((grad, Some(hess)))};
+   }
+
+   if no_descent_direction || grad_o_h{ 
+        None
+   } else {
+        Some((grad, Some(hess)))
+   }
+}
+
+// an analytic gradient supplied by the objective, with the Hessian estimated by central 
+// differences when the stencil fits in the bounds. There is no stencil to show that x is a local
+// minimum, so only the gradient-versus-stepsize test applies
+fn analytic_gradient<T, E>(mse: &mut E, result: &OptimResult<T>, h: T, config: &FilterConfig, grad: T, stencil_fits: bool) 
+    -> Option<(T, Option<T>)>
+    where T: Float, E: Objective<T>
+{
+   if !grad.is_finite() || grad.abs() <= cast::<T>(config.grad_h_ratio)*h{
+       return None;
+   }
+
+   if !stencil_fits{
+       return Some((grad, None));
+   }
+
+   let (mse_right, mse_left) = mse.mse_pair(result.x + h, result.x - h, h);
+   let hess = (mse_right + mse_left - cast::<T>(2.0)*result.mse)/(h*h);
+
+   Some((grad, if hess.is_finite() {Some(hess)} else {None}))
+}
+
+// Richardson extrapolation of the central differences at h and h/2
+fn richardson_gradient<T, E>(mse: &mut E, result: &OptimResult<T>, h: T, config: &FilterConfig) 
+    -> Option<(T, Option<T>)>
+    where T: Float, E: Objective<T>
+{
+   let c = cast::<T>;
+
+   let mse_centre    = result.mse;
+   let [mse_right, mse_left, mse_half_right, mse_half_left] 
+       = mse.mse_quad([result.x + h, result.x - h, result.x + c(0.5)*h, result.x - c(0.5)*h], h);
+
+   if !all_finite(&[mse_right, mse_left, mse_half_right, mse_half_left]){
+       return None;
+   }
+
+   let d_full = (mse_right - mse_left)/(c(2.0)*h);
+   let d_half = (mse_half_right - mse_half_left)/h;
+
+   let grad = (c(4.0)*d_half - d_full)/c(3.0);
+   let hess = (mse_right + mse_left - c(2.0)*mse_centre)/(h*h);
+
+   // the centre has to beat all four neighbours for there to be no descent direction
+   let no_descent_direction = mse_right >= mse_centre && mse_left >= mse_centre
+                           && mse_half_right >= mse_centre && mse_half_left >= mse_centre;
+   let grad_o_h = grad.abs() <= cast::<T>(config.grad_h_ratio)*h;
+
+   if no_descent_direction || grad_o_h{ 
+        None
+   } else {
+        Some((grad, Some(hess)))
+   }
+}
+
+// fourth order central differences on the five point stencil
+fn fourth_order_gradient<T, E>(mse: &mut E, result: &OptimResult<T>, h: T, config: &FilterConfig) 
+    -> Option<(T, Option<T>)>
+    where T: Float, E: Objective<T>
+{
+   let c = cast::<T>;
+
+   let mse_centre    = result.mse;
+   let [mse_right, mse_left, mse_far_right, mse_far_left] 
+       = mse.mse_quad([result.x + h, result.x - h, result.x + c(2.0)*h, result.x - c(2.0)*h], h);
+
+   if !all_finite(&[mse_right, mse_left, mse_far_right, mse_far_left]){
+       return None;
+   }
+
+   let grad = (-mse_far_right + c(8.0)*mse_right - c(8.0)*mse_left + mse_far_left)/(c(12.0)*h);
+   let hess = (-mse_far_right + c(16.0)*mse_right - c(30.0)*mse_centre + c(16.0)*mse_left - mse_far_left)/(c(12.0)*h*h);
+
+   // the centre has to beat all four neighbours for there to be no descent direction
+   let no_descent_direction = mse_right >= mse_centre && mse_left >= mse_centre
+                           && mse_far_right >= mse_centre && mse_far_left >= mse_centre;
+   let grad_o_h = grad.abs() <= cast::<T>(config.grad_h_ratio)*h;
+
+   if no_descent_direction || grad_o_h{ 
+        None
+   } else {
+        Some((grad, Some(hess)))
+   }
+}
+
+// a first order difference using only x and x + h, or x - h if x + h is out of bounds. With a 
+// single off-centre point there is no way to tell that x is a local minimum, so only the 
+// gradient-versus-stepsize test applies
+fn forward_gradient<T, E>(mse: &mut E, result: &OptimResult<T>, h: T, config: &FilterConfig, forward: bool) 
+    -> Option<(T, Option<T>)>
+    where T: Float, E: Objective<T>
+{
+   let s = if forward {h} else {-h};
+
+   if !config.feasible(result.x + s){
+       return None;
+   }
+
+   let mse_next = mse.mse(result.x + s, h);
+
+   if !mse_next.is_finite(){
+       return None;
+   }
+
+   let grad = (mse_next - result.mse)/s;
+
+   if grad.abs() <= cast::<T>(config.grad_h_ratio)*h{
+        None
+   } else {
+        Some((grad, None))
+   }
+}
+
+// second order one-sided differences, sampling to the right of x if forward is set and to the
+// left otherwise
+fn one_sided_gradient<T, E>(mse: &mut E, result: &OptimResult<T>, h: T, config: &FilterConfig, forward: bool) 
+    -> Option<(T, Option<T>)>
+    where T: Float, E: Objective<T>
+{
+   let c = cast::<T>;
+   let s = if forward {h} else {-h};
+
+   // the box is too narrow to fit the stencil on either side
+   if !config.feasible(result.x + s) || !config.feasible(result.x + c(2.0)*s){
+       return None;
+   }
+
+   let mse_centre = result.mse;
+   let (mse_near, mse_far) = mse.mse_pair(result.x + s, result.x + c(2.0)*s, h);
+
+   if !all_finite(&[mse_near, mse_far]){
+       return None;
+   }
+
+   let grad = (c(4.0)*mse_near - mse_far - c(3.0)*mse_centre)/(c(2.0)*s);
+   let hess = (mse_far - c(2.0)*mse_near + mse_centre)/(h*h);
+
+   // the only descent directions available may point out of the box
+   let no_descent_direction = mse_near >= mse_centre && mse_far >= mse_centre;
+   let grad_o_h = grad.abs() <= cast::<T>(config.grad_h_ratio)*h;
+
+   if no_descent_direction || grad_o_h{ 
+        None
+   } else {
+        Some((grad, Some(hess)))
+   }
+}
+
+// the outcome of trying a single step in a line search
+enum Trial<T>{
+    // the step satisfies the Armijo condition
+    Accepted(OptimResult<T>),
+    // the step fails the Armijo condition, giving this objective value
+    Rejected(T),
+    // x is already on the bound that the step pushes towards, so no step can make progress, or the
+    // step is shorter than min_step_frac allows
+    Stuck,
+}
+
+// try the step x + a*p, projected onto the bounds, against the Armijo condition, which is applied
+// to the step actually taken
+fn armijo_trial<T, E>(mse: &mut E, start: &OptimResult<T>, p: T, grad: T, h: T, a: T, config: &FilterConfig) -> Trial<T>
+    where T: Float, E: Objective<T>
+{
+    let x        = start.x;
+    let mse_old  = start.mse;
+
+    // a step this small barely moves x, so it's better to give up and shrink the stencil
+    if a < cast(config.min_step_frac){
+        return Trial::Stuck;
+    }
+
+    let x_trial          = x + a*p;
+    let x_new            = x_trial.max(cast(config.lower)).min(cast(config.upper));
+
+    if x_new == x{
+        return Trial::Stuck;
+    }
+
+    let step             = if x_new == x_trial {a*p} else {x_new - x};
+    let mse_new          = mse.mse(x_new, h);
+
+    // the step has wandered somewhere the objective can't be evaluated, so try a shorter one
+    if !mse_new.is_finite(){
+        return Trial::Rejected(mse_new);
+    }
+
+    let required_decrease =  cast::<T>(config.armijo_constant)*step*grad;
+    let actual_decrease = mse_new - mse_old;
+
+    if actual_decrease <= required_decrease{
+        Trial::Accepted(OptimResult{x: x_new, mse: mse_new}) 
+    } else {
+        Trial::Rejected(mse_new)
+    }
+}
+
+// A backtracking line search that attempts to find a point that satisfies the 
+// Armijo Condition. Since only an approximate gradient is used, this search is not guaranteed to a
+// actually succeed. Trial points are projected onto the bounds. The search starts from a point 
+// whose objective value, at stepsize h, is already known.
+//
+// With allow_expansion set, a successful full step is followed by doubling the step, up to
+// MAX_EXPANSION times its length, for as long as the Armijo condition holds and the objective 
+// keeps improving
+fn backtracking_line_search<T, E>(mse: &mut E, start: &OptimResult<T>, p: T, grad: T, h: T, config: &FilterConfig) 
+    -> Option<OptimResult<T>> 
+    where T: Float, E: Objective<T>
+{
+    for i in 0..config.max_line_search_iters{
+
+        let a = power(cast::<T>(config.line_search_reduction), i); 
+
+        // the step has shrunk to nothing, so no later trial can do better
+        if a == T::zero(){
+            return None;
+        }
+
+        match armijo_trial(mse, start, p, grad, h, a, config){
+            Trial::Accepted(result) if i == 0 && config.allow_expansion => {
+                return Some(expand_step(mse, start, p, grad, h, result, config));
+            },
+            Trial::Accepted(result) => return Some(result),
+            Trial::Rejected(_)      => (),
+            Trial::Stuck            => return None,
+        }
+    }
+
+    None
+}
+
+// keep doubling an accepted full step while that continues to pay off
+fn expand_step<T, E>(mse: &mut E, start: &OptimResult<T>, p: T, grad: T, h: T, accepted: OptimResult<T>,
+                     config: &FilterConfig) -> OptimResult<T>
+    where T: Float, E: Objective<T>
+{
+    let mut best = accepted;
+    let mut a    = cast::<T>(2.0);
+
+    while a <= cast(MAX_EXPANSION){
+        match armijo_trial(mse, start, p, grad, h, a, config){
+            // a step clamped to the bound lands on the same point as the previous one
+            Trial::Accepted(result) if result.mse < best.mse && result.x != best.x => best = result,
+            _                                                                   => break,
+        }
+
+        a = a*cast(2.0);
+    }
+
+    best
+}
+
+// A line search for the weak Wolfe conditions. The step grows while it is too short to satisfy 
+// the curvature condition and is bisected once an upper limit on it has been found. As with the 
+// backtracking search, trial points are projected onto the bounds; a trial on the boundary, or 
+// too close to it to difference, is accepted on sufficient decrease alone
+#[allow(clippy::too_many_arguments)]
+fn wolfe_line_search<T, E>(mse: &mut E, start: &OptimResult<T>, p: T, grad: T, h: T, c1: f64, c2: f64,
+                           config: &FilterConfig) -> Option<OptimResult<T>> 
+    where T: Float, E: Objective<T>
+{
+    let x     = start.x;
+    let two   = cast::<T>(2.0);
+    let slope = p*grad;
+
+    let mut a  = T::one();
+    let mut lo = T::zero();
+    let mut hi = None;
+
+    for _i in 0..config.max_line_search_iters{
+
+        let x_trial = x + a*p;
+        let x_new   = x_trial.max(cast(config.lower)).min(cast(config.upper));
+
+        if x_new == x{
+            return None;
+        }
+
+        let step    = if x_new == x_trial {a*p} else {x_new - x};
+        let mse_new = mse.mse(x_new, h);
+
+        // a non-finite value is treated like insufficient decrease, so the step is shortened
+        if !mse_new.is_finite() || mse_new - start.mse > cast::<T>(c1)*step*grad{
+            hi = Some(a);
+        } else {
+            let clamped = x_new != x_trial;
+
+            if clamped || !config.feasible(x_new + h) || !config.feasible(x_new - h){
+                return Some(OptimResult{x: x_new, mse: mse_new});
+            }
+
+            let (mse_right, mse_left) = mse.mse_pair(x_new + h, x_new - h, h);
+            let grad_new = (mse_right - mse_left)/(two*h);
+
+            // without a usable gradient at the trial point, settle for sufficient decrease
+            if !grad_new.is_finite(){
+                return Some(OptimResult{x: x_new, mse: mse_new});
+            }
+
+            if grad_new*p >= cast::<T>(c2)*slope{
+                return Some(OptimResult{x: x_new, mse: mse_new});
+            }
+
+            // still descending steeply, so the step was too short
+            lo = a;
+        }
+
+        a = match hi{
+            Some(hi) => (lo + hi)/two,
+            None     => two*a,
+        };
+    }
+
+    None
+}
+
+// A search on the bracket [0, hi] for a step satisfying the Armijo condition. The first trial is 
+// the full step hi = 1, and each rejected trial becomes the new upper end of the bracket, so that
+// the next trial is its midpoint
+fn bisection_line_search<T, E>(mse: &mut E, start: &OptimResult<T>, p: T, grad: T, h: T, max_iters: usize,
+                               config: &FilterConfig) -> Option<OptimResult<T>> 
+    where T: Float, E: Objective<T>
+{
+    let mut hi = T::one();
+
+    for _i in 0..max_iters{
+        match armijo_trial(mse, start, p, grad, h, hi, config){
+            Trial::Accepted(result) => return Some(result),
+            Trial::Rejected(_)      => hi = hi/cast(2.0),
+            Trial::Stuck            => return None,
+        }
+    }
+
+    None
+}
+
+// backtracking with quadratic interpolation of the objective along the search direction
+fn quadratic_line_search<T, E>(mse: &mut E, start: &OptimResult<T>, p: T, grad: T, h: T, config: &FilterConfig) 
+    -> Option<OptimResult<T>> 
+    where T: Float, E: Objective<T>
+{
+    let slope = p*grad;
+    let mut a = T::one();
+
+    for _i in 0..config.max_line_search_iters{
+        match armijo_trial(mse, start, p, grad, h, a, config){
+            Trial::Accepted(result)  => return Some(result),
+            Trial::Stuck             => return None,
+            Trial::Rejected(mse_new) => {
+                // the quadratic q(s) with q(0) = mse, q'(0) = slope and q(a) = mse_new
+                let curvature = mse_new - start.mse - slope*a;
+                let a_min     = -slope*a*a/(cast::<T>(2.0)*curvature);
+
+                // don't let the step collapse in a single trial
+                let in_range = a_min.is_finite() && a_min > cast::<T>(QUADRATIC_MIN_FRACTION)*a && a_min < a;
+
+                a = if in_range {a_min} else {a*cast(config.line_search_reduction)};
+            },
+        }
+    }
+
+    None
+}
+
+// Armijo backtracking from start, measuring the decrease from the largest of the recent objective
+// values instead of from start.mse. The values of the trial points themselves are unaffected
+fn nonmonotone_line_search<T, E>(mse: &mut E, start: &OptimResult<T>, recent: &VecDeque<T>, p: T, grad: T, h: T,
+                                 config: &FilterConfig) -> Option<OptimResult<T>> 
+    where T: Float, E: Objective<T>
+{
+    let reference = recent.iter().fold(start.mse, |max, &v| max.max(v));
+
+    backtracking_line_search(mse, &OptimResult{x: start.x, mse: reference}, p, grad, h, config)
+}
+
+// run whichever line search the configuration asks for. recent holds the objective values of the
+// latest points accepted at this stencil size, for the non-monotone search
+fn line_search<T, E>(mse: &mut E, start: &OptimResult<T>, recent: &VecDeque<T>, p: T, grad: T, h: T, 
+                     config: &FilterConfig) -> Option<OptimResult<T>> 
+    where T: Float, E: Objective<T>
+{
+    match config.line_search{
+        LineSearch::Armijo       => backtracking_line_search(mse, start, p, grad, h, config),
+        LineSearch::Wolfe{c1, c2} => wolfe_line_search(mse, start, p, grad, h, c1, c2, config),
+        LineSearch::Bisection{max_iters} => bisection_line_search(mse, start, p, grad, h, max_iters, config),
+        LineSearch::QuadraticInterp      => quadratic_line_search(mse, start, p, grad, h, config),
+        LineSearch::NonMonotone{..}      => nonmonotone_line_search(mse, start, recent, p, grad, h, config),
+    }
+}
+
+// the trust region step from start along the quasi-Newton step p, with the model 
+// grad*s + hess*s²/2 of the change in the objective (linear if there's no Hessian to trust). Each
+// rejected trial shrinks the radius and tries again, for up to max_line_search_iters trials. The 
+// radius is carried from one step to the next
+#[allow(clippy::too_many_arguments)]
+fn trust_region_step<T, E>(mse: &mut E, start: &OptimResult<T>, p: T, grad: T, hess: Option<T>, h: T, radius: &mut T, 
+                           config: &FilterConfig) -> Option<OptimResult<T>>
+    where T: Float, E: Objective<T>
+{
+    let curvature = hess.unwrap_or_else(T::zero);
+
+    for _i in 0..config.max_line_search_iters{
+        let s       = if p.abs() <= *radius {p} else {p.signum()**radius};
+        let x_trial = start.x + s;
+        let x_new   = x_trial.max(cast(config.lower)).min(cast(config.upper));
+
+        // the radius has shrunk to nothing, or x is already on the bound the step pushes towards
+        if x_new == start.x{
+            return None;
+        }
+
+        let step      = if x_new == x_trial {s} else {x_new - start.x};
+        let predicted = -(grad*step + cast::<T>(0.5)*curvature*step*step);
+        let mse_new   = mse.mse(x_new, h);
+
+        // a step to somewhere the objective can't be evaluated fits the model as badly as possible
+        let fit = if mse_new.is_finite() && predicted > T::zero() {(start.mse - mse_new)/predicted} else {T::neg_infinity()};
+
+        if fit < cast(TRUST_POOR_FIT){
+            *radius = cast::<T>(TRUST_SHRINK)*step.abs();
+        } else if fit > cast(TRUST_GOOD_FIT) && p.abs() >= *radius{
+            *radius = cast::<T>(TRUST_GROWTH)**radius;
+        }
+
+        if fit > cast(config.armijo_constant){
+            return Some(OptimResult{x: x_new, mse: mse_new});
+        }
+    }
+
+    None
+}
+
+// golden section search for the minimum of mse(·, h) on [a, b], returning the lowest finite value
+// seen, or None if there wasn't one. The search ends early if the evaluation budget runs out.
+// Option::is_none_or, which clippy prefers to map_or, needs Rust 1.82
+#[allow(clippy::unnecessary_map_or)]
+fn golden_section<T, E>(mse: &mut Counting<E>, mut a: T, mut b: T, h: T) -> Option<OptimResult<T>>
+    where T: Float, E: Objective<T>
+{
+    // 1/φ, the fraction of the bracket kept at each step
+    let ratio: T = (cast::<T>(5.0).sqrt() - T::one())/cast(2.0);
+
+    let mut best: Option<OptimResult<T>> = None;
+    let mut keep = |x: T, value: T| {
+        if value.is_finite() && best.map_or(true, |best| value < best.mse){
+            best = Some(OptimResult{x, mse: value});
+        }
+    };
+
+    let mut c  = b - ratio*(b - a);
+    let mut d  = a + ratio*(b - a);
+    let mut fc = mse.mse(c, h);
+    let mut fd = mse.mse(d, h);
+    keep(c, fc);
+    keep(d, fd);
+
+    while b - a > h && !mse.exhausted{
+        if fc < fd{
+            b  = d;
+            d  = c;
+            fd = fc;
+            c  = b - ratio*(b - a);
+            fc = mse.mse(c, h);
+            keep(c, fc);
+        } else {
+            a  = c;
+            c  = d;
+            fc = fd;
+            d  = a + ratio*(b - a);
+            fd = mse.mse(d, h);
+            keep(d, fd);
+        }
+    }
+
+    best
+}
+
+// A line search algorithm that approximately computes the gradient and Hessian using
+// finite differences. The callback is shown every iteration, and the search ends early if it asks 
+// to stop
+fn grad_search<T, E>(mse: &mut E, x: T, h: T, config: &FilterConfig, seed: Option<(T, Option<T>)>,
+                     callback: &mut dyn FnMut(&IterationRecord<T>) -> ControlFlow, out: &mut Diagnostics) 
+    -> SearchOutcome<T>
+    where T: Float, E: Objective<T>
+{
+
+    let old_result = OptimResult{ x, mse: mse.mse(x,h)};
+
+    if !old_result.mse.is_finite(){
+        return SearchOutcome{result: Err(SearchFailure::NonFinite), stopped: false, grad_converged: false, 
+                             derivatives: None, derivatives_x: x};
+    }
+
+    let mut current_result = old_result;
+    // the objective values of the latest points, the last being current_result's. Only kept up to
+    // date for the non-monotone search
+    let mut recent          = VecDeque::from(vec![old_result.mse]);
+    let mut stencil_failed  = false;
+    let mut control         = ControlFlow::Continue;
+    let mut grad_converged  = false;
+    let mut derivatives     = None;
+    let mut derivatives_x   = x;
+    // the latest Hessian estimate above hess_floor, beginning with the one from the previous 
+    // stencil size, for seed_hessian
+    let mut trusted_hess    = seed.and_then(|(_, hess)| hess).filter(|&hess| hess > cast(config.hess_floor));
+    // only used by the trust region strategy
+    let mut radius: T       = match config.step_strategy{
+                                  StepStrategy::TrustRegion{init_radius} => cast(init_radius),
+                                  StepStrategy::LineSearch               => T::zero(),
+                              };
+    let steps               = config.stencil_offsets.map(|(left, right)| (cast::<T>(left)*h, cast::<T>(right)*h));
+
+    print_table_header(config, out, h, x);
+    
+    for _i in 0..config.max_inner_iters{
+
+        // attempt to compute approximate gradient and Hessian
+        let (grad, hess) = match generate_gradient(mse, &current_result, h, steps, config){
+                       Some(gh)   => gh,
+                       None       => { print_table_row(config, out, &current_result, None);
+                                       report_stencil_failure(config, out, "Unable to clearly estimate gradient");
+                                       control = callback(&IterationRecord{h, x: current_result.x, mse: current_result.mse,
+                                                                           grad_norm: None, line_search_ok: false});
+                                       stencil_failed = true;
+                                       break},
+                };
+
+        derivatives   = Some((grad, hess));
+        derivatives_x = current_result.x;
+                        
+        if grad.abs() < cast(config.grad_tol){
+            print_table_row(config, out, &current_result, Some(grad.abs()));
+            control = callback(&IterationRecord{h, x: current_result.x, mse: current_result.mse,
+                                                grad_norm: Some(grad.abs()), line_search_ok: false});
+            grad_converged = true;
+            break;
+        }
+
+        // a vanishing gradient gives no direction to search in. Note that signum(±0.0) is ±1.0, 
+        // so this can't be left to the direction computation below
+        if let FpCategory::Zero | FpCategory::Subnormal = grad.classify(){
+            print_table_row(config, out, &current_result, Some(grad.abs()));
+            report_stencil_failure(config, out, "Gradient estimate vanished");
+            control = callback(&IterationRecord{h, x: current_result.x, mse: current_result.mse,
+                                                grad_norm: Some(grad.abs()), line_search_ok: false});
+            stencil_failed = true;
+            break;
+        }
+
+        // compute quasi-Newton search direction, or steepest descent if there's no Hessian estimate
+        // or the curvature is too small or negative to trust. Near a saddle point or with a noisy 
+        // objective, dividing by a tiny Hessian would give an enormous, meaningless step
+        let hess = match hess{
+                       Some(hess) if hess > cast(config.hess_floor) => {trusted_hess = Some(hess); Some(hess)},
+                       _ if config.seed_hessian                     => trusted_hess,
+                       _                                            => None,
+                   };
+
+        let p  = match hess{
+                    Some(hess) => -grad.signum()*grad.abs()/hess,
+                    None       => -grad.signum()*grad.abs(),
+                 };
+
+        let max_step = cast::<T>(config.max_step);
+
+        let p = if p.abs() <= max_step {p} else {-grad.signum()*max_step};    // check the search direction isn't too big
+
+        // print table row
+        print_table_row(config, out, &current_result, Some(grad.abs()));
+
+        // the search direction should always point downhill, but rounding in the direction 
+        // computation could spoil that, so fall back to steepest descent rather than panicking
+        let p = if p*grad <= T::zero() {p} else {
+            if config.verbose{
+                out.log(log::Level::Warn, 
+                        format_args!("Search direction is not a descent direction, falling back to steepest descent"));
+            }
+            -grad.signum()*grad.abs().min(max_step)
+        };
+
+        // if even steepest descent doesn't give a descent direction, the gradient estimate is unusable
+        let descent = p*grad <= T::zero();
+        if !descent{
+            report_stencil_failure(config, out, "No descent direction");
+            control = callback(&IterationRecord{h, x: current_result.x, mse: current_result.mse,
+                                                grad_norm: Some(grad.abs()), line_search_ok: false});
+            stencil_failed = true;
+            break;
+        }
+
+        // conduct a line search, or the trust region test
+        let line_search_result = match config.step_strategy{
+                                     StepStrategy::LineSearch        => line_search(mse, &current_result, &recent, p, grad, h, config),
+                                     StepStrategy::TrustRegion{..}   => trust_region_step(mse, &current_result, p, grad, hess, h,
+                                                                                          &mut radius, config),
+                                 };
+
+        control = callback(&IterationRecord{h, x: current_result.x, mse: current_result.mse,
+                                            grad_norm: Some(grad.abs()), line_search_ok: line_search_result.is_some()});
+
+        match line_search_result{
+            Some(result) => {current_result = result;
+                             if let LineSearch::NonMonotone{memory} = config.line_search{
+                                 if recent.len() == memory{
+                                     recent.pop_front();
+                                 }
+                                 recent.push_back(result.mse);
+                             }},
+            None         => {report_stencil_failure(config, out, match config.step_strategy{
+                                                                     StepStrategy::LineSearch      => "Line Search Failure",
+                                                                     StepStrategy::TrustRegion{..} => "Trust Region Failure",
+                                                                 });
+                             break;},
+        };
+
+        if control == ControlFlow::Stop{
+            break;
+        }
+
+    }
+
+    let result = if grad_converged{
+        Ok(current_result)
+    } else if current_result == old_result || current_result.mse >= old_result.mse{
+        if stencil_failed {Err(SearchFailure::Stencil)} else {Err(SearchFailure::NoImprovement)}
+    } else {
+        Ok(current_result)
+    };
+
+    SearchOutcome{result, stopped: control == ControlFlow::Stop, grad_converged, derivatives, derivatives_x}
+}
+
+// Minimise mse(x, h) over x, starting from x0 with an initial stencil size of h0. The objective
+// may be any closure, so it can capture whatever data it needs, or any type implementing 
+// Objective. An error is returned if no stencil level managed to improve on x0.
+//
+// Nothing in the algorithm is random, so for the same objective and arguments every entry point 
+// returns a bit-identical result from run to run. The randomised entry points draw from a 
+// generator seeded by the caller, so the same holds for them given the same seed
+pub fn implicit_filtering<T, F>(mse: F, x0: T, h0: T, tol: T) -> Result<OptimResult<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    implicit_filtering_with(mse, x0, h0, tol, &FilterConfig::default())
+}
+
+// implicit_filtering for an objective chosen at run time, such as one of a collection of boxed 
+// closures. A mutable reference to a closure is itself a closure, so this is the generic version 
+// instantiated once for the trait object
+pub fn implicit_filtering_dyn(mse: &mut dyn FnMut(f64, f64) -> f64, x0: f64, h0: f64, tol: f64) 
+    -> Result<OptimResult<f64>, OptimError>
+{
+    implicit_filtering(mse, x0, h0, tol)
+}
+
+// implicit_filtering with user supplied algorithm constants
+pub fn implicit_filtering_with<T, F>(mut mse: F, x0: T, h0: T, tol: T, config: &FilterConfig) 
+    -> Result<OptimResult<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    filter(&mut mse, x0, h0, tol, config, &mut |_| ControlFlow::Continue).map(|report| report.result)
+}
+
+// implicit_filtering_with, additionally reporting why the run stopped
+pub fn implicit_filtering_report<T, F>(mut mse: F, x0: T, h0: T, tol: T, config: &FilterConfig) 
+    -> Result<FilterReport<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    filter(&mut mse, x0, h0, tol, config, &mut |_| ControlFlow::Continue)
+}
+
+// implicit_filtering, additionally returning a record of every inner iteration. A failed run
+// returns the starting point along with whatever history was produced
+pub fn implicit_filtering_traced<T, F>(mut mse: F, x0: T, h0: T, tol: T) 
+    -> (OptimResult<T>, Vec<IterationRecord<T>>)
+    where T: Float, F: Objective<T>
+{
+    let mut history = Vec::new();
+
+    let result = filter(&mut mse, x0, h0, tol, &FilterConfig::default(), 
+                        &mut |record| {history.push(*record); ControlFlow::Continue});
+
+    match result{
+        Ok(report) => (report.result, history),
+        Err(_)     => (OptimResult{x: x0, mse: mse.mse(x0, h0)}, history),
+    }
+}
+
+// implicit_filtering, additionally returning the point accepted at each stencil size that 
+// improved on the last, for plotting the convergence of x. A failed run returns the starting 
+// point and an empty path
+pub fn implicit_filtering_path<T, F>(mut mse: F, x0: T, h0: T, tol: T) -> (OptimResult<T>, Vec<T>)
+    where T: Float, F: Objective<T>
+{
+    let mut path = Vec::new();
+    let mut last = x0;
+
+    // a stencil size that fails leaves the best point where it was, while one that succeeds always
+    // moves it, so the points that change are exactly the accepted ones
+    let mut accept = |x: T| if x != last {path.push(x); last = x;};
+
+    let report = run(&mut mse, Start::Point{x0, h0}, tol, &FilterConfig::default(), &mut |_| ControlFlow::Continue,
+                     &mut |state| accept(state.result.x), &mut Diagnostics::Log);
+
+    match report{
+        Ok(report) => {accept(report.result.x); (report.result, path)},
+        Err(_)     => (OptimResult{x: x0, mse: mse.mse(x0, h0)}, Vec::new()),
+    }
+}
+
+// implicit_filtering with x confined to the interval [lo, hi]. A starting point outside the 
+// interval is first projected onto it
+pub fn implicit_filtering_bounded<T, F>(mut mse: F, x0: T, h0: T, tol: T, lo: f64, hi: f64) 
+    -> Result<OptimResult<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    filter(&mut mse, x0, h0, tol, &FilterConfig::bounded(lo, hi)?, &mut |_| ControlFlow::Continue)
+        .map(|report| report.result)
+}
+
+// implicit_filtering_with, evaluating the off-centre points of each stencil concurrently. This is
+// only worthwhile when a single evaluation of the objective is expensive; the sequence of points
+// visited is identical to the sequential version
+#[cfg(feature = "parallel")]
+pub fn implicit_filtering_parallel<T, F>(mse: F, x0: T, h0: T, tol: T, config: &FilterConfig) 
+    -> Result<OptimResult<T>, OptimError>
+    where T: Float + Send, F: Fn(T, T) -> T + Sync
+{
+    filter(&mut Parallel(mse), x0, h0, tol, config, &mut |_| ControlFlow::Continue).map(|report| report.result)
+}
+
+// implicit_filtering, remembering every objective value computed so that a repeated call with 
+// exactly the same x and h is never re-evaluated. This only makes sense for deterministic objectives
+#[cfg(feature = "std")]
+pub fn implicit_filtering_cached<T, F>(mse: F, x0: T, h0: T, tol: T) -> Result<OptimResult<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    filter(&mut CachedObjective::new(mse), x0, h0, tol, &FilterConfig::default(), &mut |_| ControlFlow::Continue)
+        .map(|report| report.result)
+}
+
+// implicit_filtering for an objective that can be evaluated at complex x, using the complex step
+// derivative Im(mse(x + ih, h))/h in place of finite differences for the gradient. The objective
+// should be the analytic continuation of the real one, so that mse(x, h) is real for real x
+pub fn implicit_filtering_complex<F>(mse: F, x0: f64, h0: f64, tol: f64) -> Result<OptimResult<f64>, OptimError>
+    where F: FnMut(Complex<f64>, f64) -> Complex<f64>
+{
+    let config = FilterConfig{gradient_scheme: GradientScheme::ComplexStep, ..FilterConfig::default()};
+
+    filter(&mut ComplexStep(mse), x0, h0, tol, &config, &mut |_| ControlFlow::Continue).map(|report| report.result)
+}
+
+// implicit_filtering, calling the callback at the end of every inner iteration. If the callback
+// returns ControlFlow::Stop the run ends immediately with the best result found so far
+pub fn implicit_filtering_with_callback<T, F>(mut mse: F, x0: T, h0: T, tol: T, 
+                                              callback: &mut dyn FnMut(&IterationRecord<T>) -> ControlFlow) 
+    -> Result<OptimResult<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    filter(&mut mse, x0, h0, tol, &FilterConfig::default(), callback).map(|report| report.result)
+}
+
+// The search at a single, fixed stencil size h that implicit_filtering repeats as the stencil 
+// shrinks: quasi-Newton steps from x0 using finite difference derivatives over the stencil, until
+// the stencil fails, the line search fails or config.max_inner_iters steps have been taken. This is
+// equivalent to the first stencil size of implicit_filtering_with with h0 = h
+pub fn optimize_at_stencil<T, F>(mut mse: F, x0: T, h: T, config: &FilterConfig) -> Result<OptimResult<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    config.validate()?;
+
+    if config.direction == OptimDirection::Maximize{
+        return search_at_stencil(&mut Negated(&mut mse), x0, h, config)
+                   .map(|result| OptimResult{x: result.x, mse: -result.mse});
+    }
+
+    search_at_stencil(&mut mse, x0, h, config)
+}
+
+// optimize_at_stencil for an objective that is always minimised
+fn search_at_stencil<T, E>(mse: &mut E, x0: T, h: T, config: &FilterConfig) -> Result<OptimResult<T>, OptimError>
+    where T: Float, E: Objective<T>
+{
+    let x0 = x0.max(cast(config.lower)).min(cast(config.upper));
+
+    let mut mse = Counting::new(mse, config.max_evals);
+
+    match grad_search(&mut mse, x0, h, config, None, &mut |_| ControlFlow::Continue, &mut Diagnostics::Log).result{
+        Ok(result)                        => Ok(result),
+        Err(SearchFailure::Stencil)       => Err(OptimError::StencilFailedEverywhere),
+        Err(SearchFailure::NoImprovement) => Err(OptimError::NoImprovement),
+        Err(SearchFailure::NonFinite)     => Err(OptimError::NonFiniteObjective),
+    }
+}
+
+// implicit_filtering_report, writing the diagnostic table and failure messages to out rather 
+// than to the log, for example to capture them in a Vec<u8> or a file
+#[cfg(feature = "std")]
+pub fn implicit_filtering_to<T, F>(mse: F, x0: T, h0: T, tol: T, config: &FilterConfig, out: &mut dyn Write) 
+    -> Result<FilterReport<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    implicit_filtering_fmt(mse, x0, h0, tol, config, &mut IoWriter(out))
+}
+
+// implicit_filtering_to for a fmt::Write, such as a String, or a serial port on a target without
+// the standard library
+pub fn implicit_filtering_fmt<T, F>(mut mse: F, x0: T, h0: T, tol: T, config: &FilterConfig, out: &mut dyn fmt::Write) 
+    -> Result<FilterReport<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    filter_to(&mut mse, x0, h0, tol, config, &mut |_| ControlFlow::Continue, &mut Diagnostics::Writer(out))
+}
+
+// implicit_filtering_report, passing the state of the run to checkpoint after each stencil size
+// that doesn't end it, so that resume_from can carry on from there
+pub fn implicit_filtering_checkpointed<T, F, C>(mut mse: F, x0: T, h0: T, tol: T, config: &FilterConfig, mut checkpoint: C) 
+    -> Result<FilterReport<T>, OptimError>
+    where T: Float, F: Objective<T>, C: FnMut(&OuterLevelState<T>)
+{
+    run(&mut mse, Start::Point{x0, h0}, tol, config, &mut |_| ControlFlow::Continue, &mut checkpoint, 
+        &mut Diagnostics::Log)
+}
+
+// Carry on a run of implicit_filtering_checkpointed from one of its checkpoints. Given the same
+// objective, tol and config, the report is the one the uninterrupted run would have produced, 
+// apart from the elapsed time
+pub fn resume_from<T, F>(state: &OuterLevelState<T>, mut mse: F, tol: T, config: &FilterConfig) 
+    -> Result<FilterReport<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    run(&mut mse, Start::Checkpoint(*state), tol, config, &mut |_| ControlFlow::Continue, &mut |_| (), 
+        &mut Diagnostics::Log)
+}
+
+// the outer loop of the algorithm, shared by all of the public entry points. The callback is
+// shown every inner iteration
+fn filter<T, E>(mse: &mut E, x0: T, h0: T, tol: T, config: &FilterConfig,
+                callback: &mut dyn FnMut(&IterationRecord<T>) -> ControlFlow) -> Result<FilterReport<T>, OptimError>
+    where T: Float, E: Objective<T>
+{
+    filter_to(mse, x0, h0, tol, config, callback, &mut Diagnostics::Log)
+}
+
+// filter, sending the diagnostics to out
+fn filter_to<T, E>(mse: &mut E, x0: T, h0: T, tol: T, config: &FilterConfig,
+                   callback: &mut dyn FnMut(&IterationRecord<T>) -> ControlFlow, out: &mut Diagnostics) 
+    -> Result<FilterReport<T>, OptimError>
+    where T: Float, E: Objective<T>
+{
+    run(mse, Start::Point{x0, h0}, tol, config, callback, &mut |_| (), out)
+}
+
+// where a run begins
+#[derive(Clone)]
+#[derive(Copy)]
+enum Start<T>{
+    Point{x0: T, h0: T},
+    Checkpoint(OuterLevelState<T>),
+}
+
+// filter_to, from either a starting point or a checkpoint, passing the state to checkpoint after
+// every stencil size that doesn't end the run
+fn run<T, E>(mse: &mut E, start: Start<T>, tol: T, config: &FilterConfig,
+             callback: &mut dyn FnMut(&IterationRecord<T>) -> ControlFlow, 
+             checkpoint: &mut dyn FnMut(&OuterLevelState<T>), out: &mut Diagnostics) 
+    -> Result<FilterReport<T>, OptimError>
+    where T: Float, E: Objective<T>
+{
+    config.validate()?;
+
+    if config.direction == OptimDirection::Minimize{
+        return minimise(mse, start, tol, config, callback, checkpoint, out);
+    }
+
+    let start = match start{
+                    Start::Checkpoint(state) => Start::Checkpoint(state.negated()),
+                    point                    => point,
+                };
+
+    let mut callback   = |record: &IterationRecord<T>| callback(&IterationRecord{mse: -record.mse, ..*record});
+    let mut checkpoint = |state: &OuterLevelState<T>| checkpoint(&state.negated());
+
+    minimise(&mut Negated(mse), start, tol, config, &mut callback, &mut checkpoint, out).map(negated_report)
+}
+
+// the outer loop itself, which always minimises mse. The config has already been validated
+fn minimise<T, E>(mse: &mut E, start: Start<T>, tol: T, config: &FilterConfig,
+                  callback: &mut dyn FnMut(&IterationRecord<T>) -> ControlFlow, 
+                  checkpoint: &mut dyn FnMut(&OuterLevelState<T>), out: &mut Diagnostics) 
+    -> Result<FilterReport<T>, OptimError>
+    where T: Float, E: Objective<T>
+{
+    let mut mse   = Counting::new(mse, config.max_evals);
+    let mut state = match start{
+                        Start::Point{x0, h0}     => FilterState::new(&mut mse, x0, h0, tol, config)?,
+                        Start::Checkpoint(saved) => {mse.count = saved.eval_count; 
+                                                     FilterState::resume(&saved, tol, &mse)},
+                    };
+
+    loop{
+        if let Some(outcome) = state.step(&mut mse, config, callback, out){
+            return outcome;
+        }
+
+        checkpoint(&state.checkpoint(&mse));
+    }
+}
+
+// the report of a run that minimised the negated objective, in terms of the objective itself
+pub(crate) fn negated_report<T: Float>(report: FilterReport<T>) -> FilterReport<T>{
+    FilterReport{result:     OptimResult{x: report.result.x, mse: -report.result.mse},
+                 final_grad: report.final_grad.map(|g| -g),
+                 final_hess: report.final_hess.map(|h| -h),
+                 ..report}
+}
+
+// The state of the outer loop between stencil sizes, so that the loop can either be run to the
+// end by minimise or a stencil size at a time by ImplicitFilterIter. The objective isn't held 
+// here but passed to each call, wrapped in the Counting that keeps track of the evaluations
+pub(crate) struct FilterState<T>{
+    start          : Instant,
+    h0             : T,
+    tol            : T,
+    // the index in the schedule of the next stencil size
+    level          : usize,
+    // the latest stencil size searched, or h0 before the first
+    h              : T,
+    old_result     : OptimResult<T>,
+    improved       : bool,
+    gradient_found : bool,
+    reason         : ConvergenceReason,
+    outer_iters    : usize,
+    stalled_levels : usize,
+    derivatives    : Option<(T, Option<T>)>,
+    // the point the derivatives were estimated at
+    derivatives_x  : T,
+    // the objective's own count of its work when the run began
+    work_start     : Option<usize>,
+    // the work done before the checkpoint the run was resumed from
+    work_carried   : usize,
+}
+
+impl<T: Float> FilterState<T>{
+    // evaluate the starting point. The config has already been validated
+    pub(crate) fn new<E>(mse: &mut Counting<E>, x0: T, h0: T, tol: T, config: &FilterConfig) 
+        -> Result<FilterState<T>, OptimError>
+        where E: Objective<T>
+    {
+        // the complex step gradient is supplied by the objective wrapper in implicit_filtering_complex
+        if config.gradient_scheme == GradientScheme::ComplexStep && !mse.grad_calls_objective(){
+            return Err(OptimError::InvalidConfig("ComplexStep requires implicit_filtering_complex"));
+        }
+
+        if !(h0.is_finite() && h0 > T::zero()){
+            return Err(OptimError::InvalidConfig("h0 must be positive and finite"));
+        }
+
+        if let StencilSchedule::Custom(sizes) = &config.schedule{
+            if sizes.iter().any(|&size| cast::<T>(size) < config.min_h()){
+                return Err(OptimError::InvalidConfig("custom stencil sizes must be no smaller than min_h"));
+            }
+        }
+
+        let start      = Instant::now();
+        let work_start = mse.work();
+
+        let x0 = x0.max(cast(config.lower)).min(cast(config.upper));
+
+        let old_result = OptimResult{x: x0, mse: mse.mse(x0,h0)};
+
+        if !old_result.mse.is_finite(){
+            return Err(OptimError::NonFiniteObjective);
+        }
+
+        Ok(FilterState{start, h0, tol, level: config.start_level, h: h0, old_result, 
+                       improved:       false,
+                       gradient_found: false,
+                       reason:         ConvergenceReason::MaxOuterIterations,
+                       outer_iters:    0,
+                       stalled_levels: 0,
+                       derivatives:    None,
+                       derivatives_x:  x0,
+                       work_start,
+                       work_carried:   0})
+    }
+
+    // carry on from a checkpoint, adding the work done before it to the work mse goes on to do
+    fn resume<E: Objective<T>>(saved: &OuterLevelState<T>, tol: T, mse: &Counting<E>) -> FilterState<T>{
+        FilterState{start:          Instant::now(),
+                    h0:             saved.h0,
+                    tol,
+                    level:          saved.outer_index + 1,
+                    h:              saved.h,
+                    old_result:     saved.result,
+                    improved:       saved.improved,
+                    gradient_found: saved.gradient_found,
+                    reason:         saved.reason,
+                    outer_iters:    saved.outer_iters,
+                    stalled_levels: saved.stalled_levels,
+                    derivatives:    saved.derivatives,
+                    derivatives_x:  saved.derivatives_x,
+                    work_start:     mse.work(),
+                    work_carried:   saved.objective_work.unwrap_or(0)}
+    }
+
+    // the work done by mse since the run began
+    fn work_done<E: Objective<T>>(&self, mse: &Counting<E>) -> Option<usize>{
+        mse.work().map(|work| work.saturating_sub(self.work_start.unwrap_or(0)) + self.work_carried)
+    }
+
+    // the state after the latest stencil size, from which resume carries on
+    fn checkpoint<E: Objective<T>>(&self, mse: &Counting<E>) -> OuterLevelState<T>{
+        OuterLevelState{h:              self.h,
+                        result:         self.old_result,
+                        outer_index:    self.level - 1,
+                        h0:             self.h0,
+                        outer_iters:    self.outer_iters,
+                        improved:       self.improved,
+                        gradient_found: self.gradient_found,
+                        reason:         self.reason,
+                        stalled_levels: self.stalled_levels,
+                        derivatives:    self.derivatives,
+                        derivatives_x:  self.derivatives_x,
+                        eval_count:     mse.count,
+                        objective_work: self.work_done(mse)}
+    }
+
+    // the gradient and Hessian at the final point, reusing the search's own estimates if they were
+    // made there, and otherwise differencing over the latest stencil size
+    fn final_derivatives<E: Objective<T>>(&self, mse: &mut Counting<E>, config: &FilterConfig) 
+        -> Option<(T, Option<T>)>
+    {
+        let OptimResult{x, mse: mse_centre} = self.old_result;
+
+        if self.derivatives.is_some() && self.derivatives_x == x{
+            return self.derivatives;
+        }
+
+        let c     = cast::<T>;
+        let h     = self.h;
+        let right = config.feasible(x + h);
+        let left  = config.feasible(x - h);
+
+        // x ± h, or on a bound x, x ± h and x ± 2h on the feasible side, as one_sided_gradient uses
+        let (grad, hess) = if right && left {
+                               let (mse_right, mse_left) = mse.mse_pair(x + h, x - h, h);
+
+                               ((mse_right - mse_left)/(c(2.0)*h), (mse_right + mse_left - c(2.0)*mse_centre)/(h*h))
+                           } else {
+                               let s = if right {h} else {-h};
+
+                               if !config.feasible(x + s) || !config.feasible(x + c(2.0)*s){
+                                   return None;
+                               }
+
+                               let (mse_near, mse_far) = mse.mse_pair(x + s, x + c(2.0)*s, h);
+
+                               ((c(4.0)*mse_near - mse_far - c(3.0)*mse_centre)/(c(2.0)*s), 
+                                (mse_far - c(2.0)*mse_near + mse_centre)/(h*h))
+                           };
+
+        if !all_finite(&[grad, hess]){
+            return None;
+        }
+
+        match config.gradient_scheme{
+            GradientScheme::Forward => Some((grad, None)),
+            _                       => Some((grad, Some(hess))),
+        }
+    }
+
+    // the report of the run if it ends now for the given reason
+    fn report<E: Objective<T>>(&self, reason: ConvergenceReason, mse: &mut Counting<E>, config: &FilterConfig) 
+        -> FilterReport<T>
+    {
+        let derivatives = self.final_derivatives(mse, config);
+
+        FilterReport{result: self.old_result, reason, eval_count: mse.count, elapsed: self.start.elapsed(), 
+                     outer_iters:    self.outer_iters, 
+                     final_grad:     derivatives.map(|d| d.0), 
+                     final_hess:     derivatives.and_then(|d| d.1),
+                     objective_work: self.work_done(mse)}
+    }
+
+    // the outcome of a run that has been through the schedule, or met a tolerance. A run that never
+    // improved on its starting point tries the fallback search first
+    fn finish<E>(&mut self, mse: &mut Counting<E>, config: &FilterConfig, out: &mut Diagnostics) 
+        -> Result<FilterReport<T>, OptimError>
+        where E: Objective<T>
+    {
+        if let (false, Fallback::GoldenSection{a, b}) = (self.improved, config.fallback){
+            let a = a.max(config.lower);
+            let b = b.min(config.upper);
+
+            if a < b{
+                if config.verbose{
+                    out.log(log::Level::Warn, 
+                            format_args!("No stencil size improved on the starting point, trying a golden section \
+                                          search on [{}, {}]", a, b));
+                }
+
+                if let Some(result) = golden_section(mse, cast(a), cast(b), self.h){
+                    if result.mse < self.old_result.mse{
+                        self.old_result = result;
+                        self.improved   = true;
+                        self.reason     = ConvergenceReason::Fallback;
+                    }
+                }
+            }
+        }
+
+        // ending without meeting a tolerance means the result may be far from a minimum
+        if config.verbose && self.improved && !self.reason.tolerance_met(){
+            out.log(log::Level::Warn, 
+                    format_args!("Tolerance not met after {} outer iterations, returning the best point found", 
+                                 self.outer_iters));
+        }
+
+        if self.improved {
+            Ok(self.report(self.reason, mse, config))
+        } else if self.gradient_found {
+            Err(OptimError::NoImprovement)
+        } else {
+            Err(OptimError::StencilFailedEverywhere)
+        }
+    }
+
+    // search the next stencil size, returning the outcome of the run if it has now ended
+    pub(crate) fn step<E>(&mut self, mse: &mut Counting<E>, config: &FilterConfig,
+                          callback: &mut dyn FnMut(&IterationRecord<T>) -> ControlFlow, out: &mut Diagnostics) 
+        -> Option<Result<FilterReport<T>, OptimError>>
+        where E: Objective<T>
+    {
+        let i = self.level;
+
+        if i >= config.schedule.len(config.max_outer_iters){
+            return Some(self.finish(mse, config, out));
+        }
+
+        if let Some(limit) = config.time_limit{
+            if self.start.elapsed() >= limit{
+                return Some(Ok(self.report(ConvergenceReason::TimeLimit, mse, config)));
+            }
+        }
+
+        self.level += 1;
+
+        // differencing over a tiny stencil only amplifies rounding error, so a first stencil size 
+        // below min_h is raised to it, and the run ends once the schedule falls below it
+        let h :T = config.schedule.stencil_size(self.h0, i);
+
+        // the schedule has overflowed, so there are no more stencil sizes
+        if !h.is_finite(){
+            return Some(self.finish(mse, config, out));
+        }
+
+        let h    = if h >= config.min_h() {
+                       h
+                   } else if i == config.start_level {
+                       config.min_h()
+                   } else {
+                       // a tolerance already met still stands, since no level after it has moved x
+                       if self.reason != ConvergenceReason::ToleranceReached{
+                           self.reason = ConvergenceReason::StencilUnderflow;
+                       }
+                       return Some(self.finish(mse, config, out));
+                   };
+        self.h            = h;
+        self.outer_iters += 1;
+        
+        let seed    = if config.seed_hessian {self.derivatives} else {None};
+        let outcome = grad_search(mse, self.old_result.x, h, config, seed, callback, out);
+
+        if outcome.derivatives.is_some(){
+            self.derivatives   = outcome.derivatives;
+            self.derivatives_x = outcome.derivatives_x;
+        }
+
+        // once the budget runs out every further evaluation is NaN, which brings grad_search to a
+        // halt, so whatever it accepted before then is the best result
+        if mse.exhausted{
+            if let Ok(result) = outcome.result{
+                self.old_result = result;
+            }
+            return Some(Ok(self.report(ConvergenceReason::EvalBudgetExhausted, mse, config)));
+        }
+
+        if outcome.stopped{
+            // the caller asked for the best result so far, which may be the starting point
+            if let Ok(result) = outcome.result{
+                self.old_result = result;
+            }
+            return Some(Ok(self.report(ConvergenceReason::StoppedByCallback, mse, config)));
+        }
+
+        // if this turns out to be the last stencil size, the reason records how it ended. Once the 
+        // run has improved, a stencil that can't find a descent direction, or a line search that 
+        // can't improve on x, leaves x where the larger stencil put it, so reducing the stencil size
+        // has moved x by nothing and tol is met. The smaller stencils are still searched, and that 
+        // holds unless one of them moves x by more
+        let new_result = match outcome.result{
+                           Ok(result)                         => {self.reason = ConvergenceReason::MaxOuterIterations; 
+                                                                  result},
+                           Err(SearchFailure::Stencil) | Err(SearchFailure::NoImprovement) if self.improved 
+                                                              => {self.reason = ConvergenceReason::ToleranceReached;
+                                                                  return None},
+                           Err(SearchFailure::Stencil)        => {self.reason = ConvergenceReason::StencilFailure; 
+                                                                  return None},
+                           Err(SearchFailure::NoImprovement)  => {self.reason = ConvergenceReason::LineSearchFailure;
+                                                                  self.gradient_found = true; 
+                                                                  return None},
+                           Err(SearchFailure::NonFinite)      => return Some(Err(OptimError::NonFiniteObjective)),
+                        };
+
+        let old_result = self.old_result;
+
+        let diff   = (old_result.x - new_result.x).abs();
+        let f_diff = (old_result.mse - new_result.mse).abs()/old_result.mse.abs().max(T::min_positive_value());
+
+        if old_result.mse - new_result.mse < cast(config.stall_eps){
+            self.stalled_levels += 1;
+        } else {
+            self.stalled_levels = 0;
+        }
+
+        self.old_result = new_result;
+        self.improved   = true;
+
+        // terminate when reducing the stepsize makes no difference, or the gradient has vanished
+        let reason = if diff <= config.tol_kind.threshold(self.tol, old_result.x.abs()) {
+                         ConvergenceReason::ToleranceReached
+                     } else if config.f_tol > 0.0 && f_diff <= cast(config.f_tol) {
+                         ConvergenceReason::ObjectiveTolerance
+                     } else if outcome.grad_converged {
+                         ConvergenceReason::GradientBelowTolerance
+                     } else if self.stalled_levels > config.stall_patience {
+                         ConvergenceReason::Stalled
+                     } else {
+                         return None;
+                     };
+
+        self.reason = reason;
+
+        Some(self.finish(mse, config, out))
+    }
+}
+
+// implicit_filtering run from each of the starting points in turn, returning the index of the 
+// start that led to the lowest objective value along with its result. Starts that fail are 
+// skipped; if every one of them fails, the error from the last is returned
+pub fn implicit_filtering_multistart<T, F>(mut mse: F, starts: &[T], h0: T, tol: T) 
+    -> Result<(usize, OptimResult<T>), OptimError>
+    where T: Float, F: Objective<T>
+{
+    let mut best: Option<(usize, OptimResult<T>)> = None;
+    let mut error                                 = OptimError::InvalidConfig("at least one starting point is required");
+
+    for (i, &x0) in starts.iter().enumerate(){
+        let report = filter(&mut mse, x0, h0, tol, &FilterConfig::default(), &mut |_| ControlFlow::Continue);
+
+        match report.map(|report| report.result){
+            Ok(result) => match best{
+                Some((_, best_result)) if !result.better_than(&best_result) => (),
+                _                                                          => best = Some((i, result)),
+            },
+            Err(err)   => error = err,
+        }
+    }
+
+    best.ok_or(error)
+}
+
+// implicit_filtering_multistart from k starting points drawn uniformly from [lower, upper] by a
+// generator seeded with seed
+pub fn implicit_filtering_multistart_seeded<T, F>(mse: F, lower: T, upper: T, k: usize, seed: u64, h0: T, tol: T) 
+    -> Result<(usize, OptimResult<T>), OptimError>
+    where T: Float, F: Objective<T>
+{
+    let mut rng = SplitMix64::new(seed);
+
+    let starts: Vec<T> = (0..k).map(|_| cast(rng.uniform(show(lower), show(upper)))).collect();
+
+    implicit_filtering_multistart(mse, &starts, h0, tol)
+}
+
+// implicit_filtering from k starting points x0 + U(-radius, radius), drawn by a generator seeded 
+// with seed, returning the best result along with the number of restarts whose result improved on
+// the objective at x0. Restarts that fail are skipped; if every one of them fails, the error from 
+// the last is returned
+#[allow(clippy::too_many_arguments)]
+pub fn implicit_filtering_random_restart<T, F>(mut mse: F, x0: T, radius: T, k: usize, seed: u64, h0: T, tol: T) 
+    -> Result<(OptimResult<T>, usize), OptimError>
+    where T: Float, F: Objective<T>
+{
+    let mut rng = SplitMix64::new(seed);
+
+    let mse_x0 = mse.mse(x0, h0);
+
+    let mut best: Option<OptimResult<T>> = None;
+    let mut improved                     = 0;
+    let mut error                        = OptimError::InvalidConfig("at least one restart is required");
+
+    for _i in 0..k{
+        let start  = x0 + cast(rng.uniform(-show(radius), show(radius)));
+        let report = filter(&mut mse, start, h0, tol, &FilterConfig::default(), &mut |_| ControlFlow::Continue);
+
+        match report.map(|report| report.result){
+            Ok(result) => {
+                if result.mse < mse_x0{
+                    improved += 1;
+                }
+                match best{
+                    Some(best_result) if !result.better_than(&best_result) => (),
+                    _                                                     => best = Some(result),
+                }
+            },
+            Err(err)   => error = err,
+        }
+    }
+
+    best.map(|best| (best, improved)).ok_or(error)
+}
+
+// implicit_filtering restarted from the result of a previous run, beginning at stencil level 
+// start_level of the usual schedule, i.e. at a stencil size of h0*STENCIL_REDUCTION^start_level
+pub fn implicit_filtering_warm<T, F>(mut mse: F, prev: &OptimResult<T>, h0: T, tol: T, start_level: usize) 
+    -> Result<OptimResult<T>, OptimError>
+    where T: Float, F: Objective<T>
+{
+    let config = FilterConfig{start_level, ..FilterConfig::default()};
+
+    filter(&mut mse, prev.x, h0, tol, &config, &mut |_| ControlFlow::Continue).map(|report| report.result)
+}
+
+// Suggest an initial stencil size for a run starting at x0. Central differences are taken at 
+// stepsizes decreasing by factors of ten from max(|x0|, 1). At large stepsizes the estimate of
+// the gradient is distorted by curvature, so it changes as the stepsize shrinks; at small ones it
+// is swamped by noise in the objective, so it changes erratically. The largest stepsize whose 
+// estimate agrees with that at the next smaller one is taken to lie between the two regimes. If
+// there is no such stepsize, a tenth of the largest is returned
+pub fn estimate_h0<T, F>(mse: &mut F, x0: T) -> T
+    where T: Float, F: Objective<T>
+{
+    let ten   = cast::<T>(10.0);
+    let scale = x0.abs().max(T::one());
+
+    let mut central_difference = |h: T| {
+        let (mse_right, mse_left) = mse.mse_pair(x0 + h, x0 - h, h);
+        (mse_right - mse_left)/(cast::<T>(2.0)*h)
+    };
+
+    let mut h    = scale;
+    let mut grad = central_difference(h);
+
+    for _i in 0..H0_TRIALS{
+        let grad_next = central_difference(h/ten);
+        let agreement = cast::<T>(H0_AGREEMENT)*grad.abs().max(grad_next.abs());
+
+        if grad.is_finite() && grad != T::zero() && (grad - grad_next).abs() <= agreement{
+            return h;
+        }
+
+        h    = h/ten;
+        grad = grad_next;
+    }
+
+    scale/ten
+}
+
+// Evaluate the objective at n evenly spaced points from x_range.start to x_range.end inclusive, 
+// with a stencil size of h, returning the (x, mse) pairs in order. Useful for seeing where the 
+// local minima lie before choosing starting points for implicit_filtering_multistart. A single 
+// point is taken at the start of the range
+pub fn landscape_scan<F>(mut mse: F, x_range: Range<f64>, n: usize, h: f64) -> Vec<(f64, f64)>
+    where F: Objective<f64>
+{
+    let spacing = if n > 1 {(x_range.end - x_range.start)/(n - 1) as f64} else {0.0};
+
+    (0..n).map(|i| {
+              // the last point is placed exactly, rather than accumulating rounding in the spacing
+              let x = if i + 1 == n && n > 1 {x_range.end} else {x_range.start + i as f64*spacing};
+              (x, mse.mse(x, h))
+          })
+          .collect()
+}
+
+// The same as implicit_filtering, except that a failed run hands back the starting point
+// instead of an error
+pub fn implicit_filtering_or_default<T, F>(mut mse: F, x0: T, h0: T, tol: T) -> OptimResult<T>
+    where T: Float, F: Objective<T>
+{
+    match filter(&mut mse, x0, h0, tol, &FilterConfig::default(), &mut |_| ControlFlow::Continue){
+        Ok(report) => report.result,
+        Err(_)     => OptimResult{x: x0, mse: mse.mse(x0, h0)},
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    // the default configuration without the diagnostic table
+    fn quiet() -> FilterConfig{
+        FilterConfig{verbose: false, ..FilterConfig::default()}
+    }
+
+    #[test]
+    fn quadratic_reaches_tolerance(){
+        let report = implicit_filtering_report(|x: f64, _h: f64| (x - 1.0).powi(2), 1.5, 0.1, 1e-7, &quiet()).unwrap();
+
+        assert_eq!(report.reason, ConvergenceReason::ToleranceReached);
+        assert!((report.result.x - 1.0).abs() < 1e-6);
+        assert!(report.outer_iters >= 1);
+    }
+
+    #[test]
+    fn smooth_objectives_reach_tolerance(){
+        let cosh = implicit_filtering_report(|x: f64, _h: f64| (x - 1.0).cosh(), 2.0, 0.1, 1e-7, &quiet()).unwrap();
+        assert_eq!(cosh.reason, ConvergenceReason::ToleranceReached);
+
+        let rosenbrock = |x: f64, _h: f64| (1.0 - x).powi(2) + 100.0*(x*x - x).powi(2);
+        let report     = implicit_filtering_report(rosenbrock, 0.8, 0.1, 1e-7, &quiet()).unwrap();
+        assert_eq!(report.reason, ConvergenceReason::ToleranceReached);
+        assert!((report.result.x - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn flat_objective_is_a_stencil_failure(){
+        let result = implicit_filtering_report(|_x: f64, _h: f64| 1.0, 0.0, 0.1, 1e-7, &quiet());
+
+        assert_eq!(result, Err(OptimError::StencilFailedEverywhere));
+    }
+
+    #[test]
+    fn failed_line_search_after_improving_reaches_tolerance(){
+        // once x reaches 1, the smaller stencils see a dip at x + h that no line search step finds
+        let dipped = |x: f64, h: f64| {
+            let dip = if h < 0.3 && x > 1.0 && (x - 1.0 - h).abs() < 1e-12 {0.2} else {0.0};
+            (x - 1.0).powi(2) - dip
+        };
+        let mut dipped = dipped;
+        let mut failed = 0;
+        let report     = filter(&mut dipped, 3.0, 0.5, 1e-7, &quiet(), &mut |record: &IterationRecord<f64>| {
+                             if record.grad_norm.is_some() && !record.line_search_ok {failed += 1}
+                             ControlFlow::Continue
+                         }).unwrap();
+
+        assert!(failed > 0);
+        assert_eq!(report.result.x, 1.0);
+        assert_eq!(report.reason, ConvergenceReason::ToleranceReached);
+    }
+
+    #[test]
+    fn exhausted_schedule_reports_max_outer_iterations(){
+        let config = FilterConfig{max_outer_iters: 4, ..quiet()};
+        let report = implicit_filtering_report(|x: f64, _h: f64| (x - 1.0).powi(4), 3.0, 0.1, 1e-12, &config).unwrap();
+
+        assert_eq!(report.reason, ConvergenceReason::MaxOuterIterations);
+        assert_eq!(report.outer_iters, 4);
+    }
+
+    #[test]
+    fn unmet_tolerance_is_warned_about_on_every_exit(){
+        let quartic = |x: f64, _h: f64| (x - 1.0).powi(4);
+
+        // running out of stencil sizes, and falling below min_h
+        for config in &[FilterConfig{max_outer_iters: 4, ..FilterConfig::default()}, FilterConfig::default()]{
+            let mut out = String::new();
+            let report  = implicit_filtering_fmt(quartic, 3.0, 0.1, 1e-12, config, &mut out).unwrap();
+
+            assert!(!report.reason.tolerance_met());
+            assert!(out.contains("Tolerance not met"), "no warning for {:?}", report.reason);
+        }
+
+        let mut out = String::new();
+        implicit_filtering_fmt(|x: f64, _h: f64| (x - 1.0).powi(2), 1.5, 0.1, 1e-7, &FilterConfig::default(), &mut out).unwrap();
+        assert!(!out.contains("Tolerance not met"));
+    }
+
+    #[test]
+    fn tiny_h0_ends_on_the_min_h_cut_off(){
+        let quartic = |x: f64, _h: f64| (x - 1.0).powi(4);
+        let report  = implicit_filtering_report(quartic, 3.0, 1e-10, 1e-12, &quiet()).unwrap();
+        // the first stencil is raised to min_h rather than differencing over 1e-10
+        assert!(report.result.x.is_finite() && report.result.x < 3.0);
+        assert_eq!(report.reason, ConvergenceReason::StencilUnderflow);
+        assert_eq!(report.outer_iters, 1);
+    }
+
+    #[test]
+    fn unusable_h0_is_rejected(){
+        for &h0 in &[0.0, -0.1, f64::NAN, f64::INFINITY]{
+            assert_eq!(implicit_filtering_with(|x: f64, _h: f64| x*x, 1.0, h0, 1e-7, &quiet()),
+                       Err(OptimError::InvalidConfig("h0 must be positive and finite")));
+        }
+    }
+
+    #[test]
+    fn custom_sizes_below_min_h_are_rejected(){
+        let config = FilterConfig{schedule: StencilSchedule::Custom(vec![0.5, 0.1, 1e-9, 0.01]), ..quiet()};
+
+        assert_eq!(config.validate(), 
+                   Err(OptimError::InvalidConfig("custom stencil sizes must be no smaller than min_h")));
+        assert!(implicit_filtering_with(|x: f64, _h: f64| x*x, 1.0, 0.1, 1e-7, &config).is_err());
+    }
+
+    #[test]
+    fn complex_step_gradients_count_towards_max_evals(){
+        let calls  = core::cell::Cell::new(0);
+        let mse    = |z: Complex<f64>, _h: f64| {
+                         calls.set(calls.get() + 1);
+                         assert!(calls.get() <= 20, "the objective was called past max_evals");
+                         (z - 1.0)*(z - 1.0)
+                     };
+        let config = FilterConfig{gradient_scheme: GradientScheme::ComplexStep, max_evals: Some(20), ..quiet()};
+        let report = implicit_filtering_report(ComplexStep(mse), 3.0, 0.1, 1e-12, &config).unwrap();
+
+        assert_eq!(report.eval_count, calls.get());
+        assert!(report.eval_count <= 20);
+    }
+
+    #[test]
+    fn counting_objective_counts_complex_step_gradients(){
+        let calls    = core::cell::Cell::new(0);
+        let mut mse  = CountingObjective::new(ComplexStep(|z: Complex<f64>, _h: f64| {
+                           calls.set(calls.get() + 1);
+                           (z - 1.0)*(z - 1.0)
+                       }));
+        let config   = FilterConfig{gradient_scheme: GradientScheme::ComplexStep, ..quiet()};
+        let report   = implicit_filtering_report(&mut mse, 1.5, 0.1, 1e-7, &config).unwrap();
+
+        assert_eq!(mse.count(), calls.get());
+        assert_eq!(report.eval_count, calls.get());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_wide_stencils_match_sequential(){
+        let mse = |x: f64, _h: f64| (x - 1.0).powi(2) + 0.1*(x - 1.0).powi(4);
+
+        for scheme in [GradientScheme::Richardson, GradientScheme::CentralFourthOrder]{
+            let config = FilterConfig{gradient_scheme: scheme, ..quiet()};
+
+            assert_eq!(implicit_filtering_parallel(mse, 2.0, 0.1, 1e-7, &config),
+                       implicit_filtering_with(mse, 2.0, 0.1, 1e-7, &config));
+        }
+    }
+
+    #[test]
+    fn zero_max_outer_iters_is_rejected(){
+        let config = FilterConfig{max_outer_iters: 0, ..quiet()};
+
+        assert_eq!(config.validate(), Err(OptimError::InvalidConfig("max_outer_iters must be at least 1")));
+    }
+
+    #[test]
+    fn inner_and_line_search_iterations_are_bounded_separately(){
+        // every inner iteration of a Newton step on the quartic is accepted at once, costing the two
+        // stencil points and the trial point
+        let evals = |max_inner_iters, max_line_search_iters| {
+            let config  = FilterConfig{max_inner_iters, max_line_search_iters, ..quiet()};
+            let mut mse = CountingObjective::new(|x: f64, _h: f64| (x - 1.0).powi(4));
+            optimize_at_stencil(&mut mse, 3.0, 0.01, &config).unwrap();
+            mse.count()
+        };
+
+        assert_eq!(evals(1, 10) + 3, evals(2, 10));
+        assert_eq!(evals(2, 1), evals(2, 10));
+
+        // only the stencil points of the start are any good, so every trial of the line search is
+        // rejected
+        let evals = |max_inner_iters, max_line_search_iters| {
+            let config  = FilterConfig{max_inner_iters, max_line_search_iters, ..quiet()};
+            let mut mse = CountingObjective::new(|x: f64, _h: f64| if (x - 3.0).abs() <= 0.011 {x*x} else {100.0});
+            let _       = optimize_at_stencil(&mut mse, 3.0, 0.01, &config);
+            mse.count()
+        };
+
+        assert_eq!(evals(10, 2) + 5, evals(10, 7));
+        assert_eq!(evals(1, 4), evals(10, 4));
+    }
+
+    #[test]
+    fn closure_capturing_observations(){
+        // least squares fit of a constant to the observations, whose minimum is their mean
+        let observations = [1.2, 0.8, 1.1, 0.9, 1.5].to_vec();
+        let mse          = |x: f64, _h: f64| observations.iter().map(|y| (y - x).powi(2)).sum::<f64>();
+        let mean         = observations.iter().sum::<f64>()/observations.len() as f64;
+
+        let result = implicit_filtering(mse, 0.0, 0.1, 1e-7).unwrap();
+
+        assert!((result.x - mean).abs() < 1e-6);
+    }
+
+    #[test]
+    fn traced_history_records_every_iteration(){
+        let mse               = |x: f64, _h: f64| (x - 1.0).powi(2) + 0.1*(x - 1.0).powi(4);
+        let (result, history) = implicit_filtering_traced(mse, 3.0, 0.1, 1e-7);
+
+        let mut calls = 0;
+        implicit_filtering_with_callback(mse, 3.0, 0.1, 1e-7, &mut |_| {calls += 1; ControlFlow::Continue}).unwrap();
+
+        assert_eq!(history.len(), calls);
+        assert!(history.iter().any(|record| record.line_search_ok));
+        assert!(history.windows(2).all(|pair| pair[1].mse <= pair[0].mse));
+        assert!(history.last().unwrap().mse >= result.mse);
+    }
+
+    #[test]
+    fn objective_is_never_evaluated_outside_the_box(){
+        let boxed = |x: f64, _h: f64| {assert!((0.0..=2.0).contains(&x), "evaluated at {}", x);
+                                       (x - 1.9).powi(2)};
+
+        // the stencils about the minimum would reach past the upper bound, and the starting point
+        // is outside the box altogether
+        let inside  = implicit_filtering_bounded(boxed, 0.5, 0.5, 1e-7, 0.0, 2.0).unwrap();
+        let outside = implicit_filtering_bounded(boxed, -3.0, 0.5, 1e-7, 0.0, 2.0).unwrap();
+
+        assert!((inside.x - 1.9).abs() < 1e-6);
+        assert!((outside.x - 1.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn minimum_beyond_the_box_is_found_on_the_bound(){
+        let above = |x: f64, _h: f64| {assert!((0.0..=2.0).contains(&x), "evaluated at {}", x);
+                                       (x - 5.0).powi(2)};
+        let below = |x: f64, _h: f64| {assert!((0.0..=2.0).contains(&x), "evaluated at {}", x);
+                                       (x + 5.0).powi(2)};
+
+        let upper = implicit_filtering_bounded(above, 0.5, 0.1, 1e-7, 0.0, 2.0).unwrap();
+        let lower = implicit_filtering_bounded(below, 1.5, 0.1, 1e-7, 0.0, 2.0).unwrap();
+
+        assert_eq!(upper.x, 2.0);
+        assert_eq!(upper.mse, 9.0);
+        assert_eq!(lower.x, 0.0);
+        assert_eq!(lower.mse, 25.0);
+    }
+
+    #[test]
+    fn forward_scheme_reaches_tolerance(){
+        let config = FilterConfig{gradient_scheme: GradientScheme::Forward, ..FilterConfig::default()};
+        let result = implicit_filtering_with(|x: f64, _h: f64| (x - 1.0).powi(2), 2.0, 0.1, 1e-7, &config).unwrap();
+
+        assert!((result.x - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn forward_gradient_takes_one_evaluation(){
+        let centre      = OptimResult{x: 2.0, mse: 1.0};
+        let evaluations = |gradient_scheme| {
+            let config    = FilterConfig{gradient_scheme, ..FilterConfig::default()};
+            let mut calls = 0;
+            let (_, hess) = generate_gradient(&mut |x: f64, _h: f64| {calls += 1; (x - 1.0).powi(2)}, &centre, 0.1, None, &config)
+                                .unwrap();
+            (calls, hess.is_some())
+        };
+
+        assert_eq!(evaluations(GradientScheme::Central), (2, true));
+        assert_eq!(evaluations(GradientScheme::Forward), (1, false));
+    }
+
+    #[test]
+    fn fourth_order_gradient_beats_central_on_a_cubic(){
+        let centre = OptimResult{x: 1.0, mse: 1.0};
+        let error  = |gradient_scheme| {
+            let config    = FilterConfig{gradient_scheme, ..quiet()};
+            let (grad, _) = generate_gradient(&mut |x: f64, _h: f64| x*x*x, &centre, 0.1, None, &config).unwrap();
+            (grad - 3.0).abs()
+        };
+
+        assert!(error(GradientScheme::Central) > 1e-3);
+        assert!(error(GradientScheme::CentralFourthOrder) < 1e-12);
+    }
+
+    #[test]
+    fn quadratic_in_single_precision(){
+        let result = implicit_filtering(|x: f32, _h: f32| (x - 1.0).powi(2), 1.5, 0.1, 1e-4).unwrap();
+
+        assert!((result.x - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn default_min_h_follows_the_float_type(){
+        // tol is out of reach, so the run only ends once the schedule falls below min_h
+        let mut quartic  = |x: f32, _h: f32| (x - 1.0).powi(4);
+        let mut smallest = f32::INFINITY;
+        let report       = filter(&mut quartic, 3.0, 0.1, 0.0, &quiet(), &mut |record: &IterationRecord<f32>| {
+                               smallest = smallest.min(record.h);
+                               ControlFlow::Continue
+                           }).unwrap();
+
+        assert_eq!(report.reason, ConvergenceReason::StencilUnderflow);
+        assert!(smallest >= f32::EPSILON.sqrt());
+        assert!(smallest < 4.0*f32::EPSILON.sqrt());
+
+        let config = FilterConfig{schedule: StencilSchedule::Custom(vec![0.1, 1e-5]), ..quiet()};
+        assert!(config.validate().is_ok());
+        assert_eq!(implicit_filtering_with(|x: f32, _h: f32| x*x, 1.0, 0.1, 1e-4, &config), 
+                   Err(OptimError::InvalidConfig("custom stencil sizes must be no smaller than min_h")));
+    }
+
+    #[test]
+    fn grad_tol_ends_the_inner_search_early(){
+        // steepest descent on a shallow quadratic closes in on the minimum a little at a time
+        let iterations = |grad_tol| {
+            let config    = FilterConfig{grad_tol, hess_floor: f64::INFINITY, grad_h_ratio: 1e-6, ..quiet()};
+            let mut calls = 0;
+            let report    = filter(&mut |x: f64, _h: f64| 0.1*(x - 1.0).powi(2), 3.0, 0.1, 1e-7, &config,
+                                   &mut |_| {calls += 1; ControlFlow::Continue}).unwrap();
+            (calls, report.reason)
+        };
+
+        let (plain, _)      = iterations(0.0);
+        let (early, reason) = iterations(1e-3);
+
+        assert!(early < plain, "{} iterations with grad_tol against {} without", early, plain);
+        assert_eq!(reason, ConvergenceReason::GradientBelowTolerance);
+    }
+
+    #[test]
+    fn f_tol_ends_the_run_before_the_x_tolerance(){
+        // near the flat minimum of a quartic the objective settles long before x does
+        let quartic = |x: f64, _h: f64| (x - 1.0).powi(4) + 1.0;
+        let loose   = FilterConfig{f_tol: 1e-6, ..FilterConfig::default()};
+        let early   = implicit_filtering_report(quartic, 3.0, 0.5, 1e-12, &loose).unwrap();
+        let plain   = implicit_filtering_report(quartic, 3.0, 0.5, 1e-12, &FilterConfig::default()).unwrap();
+
+        assert_eq!(early.reason, ConvergenceReason::ObjectiveTolerance);
+        assert!(plain.reason != ConvergenceReason::ObjectiveTolerance);
+        assert!((early.result.x - 1.0).abs() > (plain.result.x - 1.0).abs());
+    }
+
+    #[test]
+    fn eval_count_matches_the_calls_made(){
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls  = AtomicUsize::new(0);
+        let mse    = |x: f64, _h: f64| {calls.fetch_add(1, Ordering::Relaxed); (x - 1.0).powi(2) + 0.1*(x - 1.0).powi(4)};
+        let report = implicit_filtering_report(mse, 3.0, 0.1, 1e-7, &quiet()).unwrap();
+
+        assert_eq!(report.eval_count, calls.load(Ordering::Relaxed));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn cached_run_never_repeats_a_stencil_point(){
+        // the calls made at each (x, h), and how many of them there were in all
+        let evaluations = |cached: bool| {
+            let mut points = Vec::new();
+            let mse        = |x: f64, h: f64| {points.push((x.to_bits(), h.to_bits())); (x - 1.0).powi(2) + 0.1*x.cos()};
+            let result     = if cached {implicit_filtering_cached(mse, 3.0, 0.5, 1e-7).unwrap()} 
+                             else {implicit_filtering(mse, 3.0, 0.5, 1e-7).unwrap()};
+            let calls      = points.len();
+            points.sort_unstable();
+            points.dedup();
+            (result, calls, points.len())
+        };
+
+        let (plain, plain_calls, plain_points)   = evaluations(false);
+        let (cached, cached_calls, cached_points) = evaluations(true);
+
+        assert!(plain_calls > plain_points);
+        assert_eq!(cached_calls, cached_points);
+        assert_eq!(cached_points, plain_points);
+        assert_eq!(cached, plain);
+    }
+
+    #[test]
+    fn custom_schedule_visits_its_sizes_in_order(){
+        let sizes    = [0.5, 0.2, 0.05, 0.01];
+        let config   = FilterConfig{schedule: StencilSchedule::Custom(sizes.to_vec()), ..quiet()};
+        let mut seen = Vec::new();
+        let mse      = |x: f64, h: f64| {if seen.last() != Some(&h) {seen.push(h)}; (x - 1.0).powi(4)};
+
+        let report = implicit_filtering_report(mse, 3.0, 1.0, 1e-12, &config).unwrap();
+
+        // the starting point is evaluated at h0
+        assert_eq!(seen[0], 1.0);
+        assert_eq!(seen[1..], sizes);
+        assert_eq!(report.outer_iters, sizes.len());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn slow_objective_runs_into_the_time_limit(){
+        let slow   = |x: f64, _h: f64| {std::thread::sleep(Duration::from_millis(2)); (x - 1.0).powi(2)};
+        let config = FilterConfig{time_limit: Some(Duration::from_millis(10)), ..quiet()};
+        let report = implicit_filtering_report(slow, 3.0, 0.5, 1e-12, &config).unwrap();
+
+        assert_eq!(report.reason, ConvergenceReason::TimeLimit);
+        assert!(report.elapsed >= Duration::from_millis(10));
+        assert!(report.result.mse < 4.0);
+    }
+
+    #[test]
+    fn wolfe_rejects_a_step_armijo_accepts(){
+        let mut quad = |x: f64, _h: f64| (x - 1.0).powi(2);
+        let start    = OptimResult{x: 3.0, mse: 4.0};
+        let config   = FilterConfig{allow_expansion: false, ..quiet()};
+
+        // a step of 0.01 towards the minimum 2 away decreases the objective enough for Armijo, but 
+        // leaves the slope nearly as steep as it was
+        let armijo = backtracking_line_search(&mut quad, &start, -0.01, 4.0, 0.01, &config).unwrap();
+        let wolfe  = wolfe_line_search(&mut quad, &start, -0.01, 4.0, 0.01, 1e-4, 0.9, &config).unwrap();
+
+        assert_eq!(armijo.x, 2.99);
+        assert!(wolfe.x < armijo.x);
+        assert!(wolfe.mse < armijo.mse);
+    }
+
+    #[test]
+    fn expansion_reaches_a_distant_minimum_sooner(){
+        // far from the minimum the quasi-Newton step is cut down to the maximum step length
+        let iterations = |allow_expansion| {
+            let config    = FilterConfig{allow_expansion, ..quiet()};
+            let mut calls = 0;
+            let report    = filter(&mut |x: f64, _h: f64| (1.0 + (x - 100.0).powi(2)).sqrt(), 0.0, 0.1, 1e-7, &config,
+                                   &mut |_| {calls += 1; ControlFlow::Continue}).unwrap();
+            (calls, report.result)
+        };
+
+        let (plain, plain_result)       = iterations(false);
+        let (expanded, expanded_result) = iterations(true);
+
+        assert!((plain_result.x - 100.0).abs() < 1e-6);
+        assert!((expanded_result.x - 100.0).abs() < 1e-6);
+        assert!(expanded < plain, "{} iterations with expansion against {} without", expanded, plain);
+    }
+
+    #[test]
+    fn longer_steps_reach_a_distant_minimum_sooner(){
+        // the stencil size at which the run first comes within 1e-6 of the minimum
+        let arrival = |max_step: f64| {
+                          let mut levels = Vec::new();
+                          implicit_filtering_checkpointed(|x: f64, _h: f64| (x - 100.0).powi(2), 0.0, 0.5, 1e-7, 
+                                                          &FilterConfig{max_step, ..quiet()}, 
+                                                          |state| levels.push(*state)).unwrap();
+                          levels.iter().position(|state| (state.result.x - 100.0).abs() < 1e-6)
+                      };
+
+        // with steps of at most 3, the ten inner iterations of each stencil size cover 30 at most, while
+        // Newton's step is taken at once
+        assert_eq!(arrival(1000.0), Some(0));
+        assert_eq!(arrival(MAX_STEP), Some(3));
+
+        let invalid = FilterConfig{max_step: 0.0, ..quiet()};
+        assert_eq!(invalid.validate(), Err(OptimError::InvalidConfig("max_step must be positive")));
+    }
+
+    #[test]
+    fn smaller_grad_h_ratio_keeps_a_shallow_search_going(){
+        // the gradient at the start is 0.01, a tenth of the first stencil size
+        let shallow = |x: f64, _h: f64| 0.01*(x - 1.0).powi(2);
+        let centre  = OptimResult{x: 1.5, mse: shallow(1.5, 0.1)};
+        let loose   = FilterConfig{grad_h_ratio: 0.05, ..quiet()};
+        let mut mse = shallow;
+
+        assert!(generate_gradient(&mut mse, &centre, 0.1, None, &quiet()).is_none());
+        let (grad, _) = generate_gradient(&mut mse, &centre, 0.1, None, &loose).unwrap();
+        assert!((grad - 0.01).abs() < 1e-12);
+
+        // so the first stencil size makes progress instead of failing at once
+        let first_level = |config: &FilterConfig| {
+                              let mut levels = Vec::new();
+                              implicit_filtering_checkpointed(shallow, 1.5, 0.1, 1e-7, config, |state| levels.push(*state))
+                                  .unwrap();
+                              levels[0].result.x
+                          };
+        assert_eq!(first_level(&quiet()), 1.5);
+        assert!((first_level(&loose) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn zero_gradient_in_a_flat_region_is_a_stencil_failure(){
+        // flat on (-1, 1), so every stencil about the start differences to exactly zero, and a slope
+        // so shallow that its gradient is subnormal, which only a grad_h_ratio of 0 lets through
+        let plateau = |x: f64, _h: f64| if x.abs() < 1.0 {1.0} else {x*x};
+        let ramp    = |x: f64, _h: f64| 1e-310*x;
+
+        for (mse, grad_h_ratio) in [(&plateau as &dyn Fn(f64, f64) -> f64, 1.0), (&ramp, 0.0)].iter(){
+            let config      = FilterConfig{grad_h_ratio: *grad_h_ratio, ..quiet()};
+            let mut history = Vec::new();
+            let result      = filter(&mut |x, h| mse(x, h), 0.3, 0.1, 1e-7, &config, 
+                                     &mut |record| {history.push(*record); ControlFlow::Continue});
+
+            // neither signum(0.0) = 1.0 nor the sign of a subnormal was taken as a direction to step in
+            assert_eq!(result.unwrap_err(), OptimError::StencilFailedEverywhere);
+            assert!(history.iter().all(|record| record.x == 0.3 && !record.line_search_ok));
+        }
+    }
+
+    #[test]
+    fn concave_region_is_left_downhill(){
+        // a double well, concave for |x| < 1/√3, so the Newton step from 0.2 would climb to the 
+        // maximum at 0
+        let wells       = |x: f64, _h: f64| x.powi(4) - 2.0*x*x;
+        let mut mse     = wells;
+        let mut history = Vec::new();
+        let report      = filter(&mut mse, 0.2, 0.1, 1e-7, &quiet(), &mut |record| {history.push(*record); ControlFlow::Continue})
+                              .unwrap();
+
+        assert!(history[0].line_search_ok);
+        assert!(history[1].x > 0.2);
+        assert!(history[1].mse < history[0].mse);
+        assert!((report.result.x - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ascent_direction_falls_back_to_steepest_descent(){
+        // with every Hessian trusted, the negative curvature of the double well at 0.2 turns the 
+        // Newton step uphill, as rounding once could
+        let wells   = |x: f64, _h: f64| x.powi(4) - 2.0*x*x;
+        let config  = FilterConfig{hess_floor: f64::NEG_INFINITY, ..FilterConfig::default()};
+        let mut out = String::new();
+        let report  = implicit_filtering_fmt(wells, 0.2, 0.1, 1e-7, &config, &mut out).unwrap();
+
+        assert!(out.contains("Search direction is not a descent direction, falling back to steepest descent"));
+        assert!((report.result.x - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nan_region_is_never_entered(){
+        // the minimum of the quadratic lies in the region where the objective is NaN
+        let blows_up    = |x: f64, _h: f64| if x > 10.0 {f64::NAN} else {(x - 12.0).powi(2)};
+        let mut mse     = blows_up;
+        let mut history = Vec::new();
+        let report      = filter(&mut mse, 5.0, 0.5, 1e-7, &quiet(), &mut |record| {history.push(*record); ControlFlow::Continue})
+                              .unwrap();
+
+        assert!(history.iter().all(|record| record.x <= 10.0 && !record.mse.is_nan()));
+        assert!(!report.result.mse.is_nan());
+        assert!(report.result.x <= 10.0 && report.result.x > 9.0);
+
+        // a run can't start from a point where the objective is NaN
+        assert_eq!(implicit_filtering_report(blows_up, 11.0, 0.5, 1e-7, &quiet()).unwrap_err(), 
+                   OptimError::NonFiniteObjective);
+    }
+
+    #[test]
+    fn results_display_to_ten_places(){
+        let result = OptimResult{x: 1.0, mse: -0.25};
+        let lost   = OptimResult{x: f64::NAN, mse: f64::INFINITY};
+
+        assert_eq!(result.to_string(), "x = +1.0000000000, mse = -0.2500000000");
+        assert_eq!(lost.to_string(), "x = NaN, mse = +inf");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn results_round_trip_through_json(){
+        let result = OptimResult{x: 1.5, mse: 0.25};
+        let json   = serde_json::to_string(&result).unwrap();
+
+        assert_eq!(json, r#"{"x":1.5,"mse":0.25}"#);
+        assert_eq!(serde_json::from_str::<OptimResult>(&json).unwrap(), result);
+
+        let report = implicit_filtering_report(|x: f64, _h: f64| (x - 1.0).powi(2), 1.5, 0.1, 1e-7, &quiet()).unwrap();
+        let json   = serde_json::to_string(&report).unwrap();
+
+        assert_eq!(serde_json::from_str::<FilterReport>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn multistart_finds_the_global_minimum(){
+        // a double well with its global minimum near x = -1 and a local one near x = 1
+        let well = |x: f64, _h: f64| (x*x - 1.0).powi(2) + 0.3*x;
+
+        for starts in &[[1.2, -1.2], [-1.2, 1.2]]{
+            let (i, result) = implicit_filtering_multistart(well, starts, 0.1, 1e-7).unwrap();
+
+            assert_eq!(starts[i], -1.2);
+            assert!(result.x < -1.0);
+        }
+
+        // a start at which the objective isn't finite is skipped
+        let (i, _) = implicit_filtering_multistart(|x: f64, h| if x > 5.0 {f64::NAN} else {well(x, h)}, 
+                                                   &[10.0, -1.2], 0.1, 1e-7).unwrap();
+        assert_eq!(i, 1);
+    }
+
+    #[test]
+    fn analytic_gradient_speeds_up_a_quartic(){
+        struct Quartic;
+
+        impl Objective for Quartic{
+            fn mse(&mut self, x: f64, _h: f64) -> f64{
+                (x - 1.0).powi(4)
+            }
+
+            fn grad(&mut self, x: f64, _h: f64) -> Option<f64>{
+                Some(4.0*(x - 1.0).powi(3))
+            }
+        }
+
+        // the exact gradient gets as close to the minimum in fewer iterations
+        let quartic    = |x: f64, _h: f64| (x - 1.0).powi(4);
+        let analytic   = implicit_filtering_report(Quartic, 3.0, 0.5, 1e-3, &quiet()).unwrap();
+        let difference = implicit_filtering_report(quartic, 3.0, 0.5, 1e-3, &quiet()).unwrap();
+
+        assert!(analytic.converged() && difference.converged());
+        assert!(analytic.eval_count < difference.eval_count);
+        assert!((analytic.result.x - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn warm_start_needs_fewer_evaluations(){
+        let mse      = |x: f64, _h: f64| (x - 1.0).powi(2) + 0.1*(x - 1.0).powi(4);
+        let mut cold = CountingObjective::new(mse);
+        let mut warm = CountingObjective::new(mse);
+
+        let prev = implicit_filtering(&mut cold, 10.0, 0.1, 1e-7).unwrap();
+        let next = implicit_filtering_warm(&mut warm, &OptimResult{x: 1.001, mse: mse(1.001, 0.1)}, 0.1, 1e-7, 4).unwrap();
+
+        assert!(warm.count() < cold.count());
+        assert!((next.x - prev.x).abs() < 1e-6);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn estimate_h0_suits_the_ode_fit(){
+        let mut mse = euler::mse_against_analytic(euler::rk2, |t| t.exp(), 5.0);
+        let h0      = estimate_h0(&mut mse, 1.5);
+
+        assert!(h0.is_finite() && h0 > 0.0);
+        assert!(h0 <= 1.5);
+    }
+
+    #[test]
+    fn richardson_gradient_is_fourth_order(){
+        let error = |gradient_scheme, h: f64| {
+            let config    = FilterConfig{gradient_scheme, ..quiet()};
+            let mut mse   = CountingObjective::new(|x: f64, _h: f64| x.exp());
+            let centre    = OptimResult{x: 0.5, mse: 0.5_f64.exp()};
+            let (grad, _) = generate_gradient(&mut mse, &centre, h, None, &config).unwrap();
+            ((grad - 0.5_f64.exp()).abs(), mse.count())
+        };
+
+        let (central, central_evals)       = error(GradientScheme::Central, 0.1);
+        let (richardson, richardson_evals) = error(GradientScheme::Richardson, 0.1);
+        let (richardson_half, _)           = error(GradientScheme::Richardson, 0.05);
+
+        assert!(richardson < central/100.0);
+        // halving h divides a fourth order error by about 16
+        assert!((richardson/richardson_half - 16.0).abs() < 1.0);
+        // the points at h/2 are counted too
+        assert_eq!(richardson_evals, central_evals + 2);
+    }
+
+    #[test]
+    fn bisection_copes_with_a_badly_scaled_step(){
+        // the full step overshoots the minimum 2 away by a factor of 50
+        let start  = OptimResult{x: 3.0, mse: 4.0};
+        let config = FilterConfig{allow_expansion: false, max_line_search_iters: 20, ..quiet()};
+
+        let mut geometric = CountingObjective::new(|x: f64, _h: f64| (x - 1.0).powi(2));
+        let mut bisection = CountingObjective::new(|x: f64, _h: f64| (x - 1.0).powi(2));
+
+        let geometric_result = backtracking_line_search(&mut geometric, &start, -100.0, 4.0, 0.01, &config).unwrap();
+        let bisection_result = bisection_line_search(&mut bisection, &start, -100.0, 4.0, 0.01, 10, &config).unwrap();
+
+        assert!(bisection.count() < geometric.count());
+        assert!(geometric_result.mse < start.mse && bisection_result.mse < start.mse);
+    }
+
+    #[test]
+    fn quadratic_interpolation_needs_fewer_trials(){
+        // on a quadratic the interpolated step after the first rejected trial is exact
+        let start  = OptimResult{x: 3.0, mse: 4.0};
+        let config = FilterConfig{allow_expansion: false, ..quiet()};
+
+        let mut geometric   = CountingObjective::new(|x: f64, _h: f64| (x - 1.0).powi(2));
+        let mut interpolant = CountingObjective::new(|x: f64, _h: f64| (x - 1.0).powi(2));
+
+        backtracking_line_search(&mut geometric, &start, -10.0, 4.0, 0.01, &config).unwrap();
+        let result = quadratic_line_search(&mut interpolant, &start, -10.0, 4.0, 0.01, &config).unwrap();
+
+        assert_eq!(interpolant.count(), 2);
+        assert!(interpolant.count() < geometric.count());
+        assert!((result.x - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn seeded_multistart_is_reproducible(){
+        let well = |x: f64, _h: f64| (x*x - 1.0).powi(2) + 0.3*x;
+
+        let first  = implicit_filtering_multistart_seeded(well, -2.0, 2.0, 5, 42, 0.1, 1e-7).unwrap();
+        let second = implicit_filtering_multistart_seeded(well, -2.0, 2.0, 5, 42, 0.1, 1e-7).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first.1.x.to_bits(), second.1.x.to_bits());
+    }
+
+    #[test]
+    fn random_restart_escapes_the_local_basin(){
+        // starting in the basin of the local minimum near x = 1
+        let well = |x: f64, _h: f64| (x*x - 1.0).powi(2) + 0.3*x;
+
+        let (result, improved) = implicit_filtering_random_restart(well, 1.0, 2.5, 8, 7, 0.1, 1e-7).unwrap();
+
+        assert!(result.x < -1.0);
+        assert!((1..=8).contains(&improved));
+    }
+
+    #[test]
+    fn stall_ends_a_run_near_a_flat_minimum(){
+        // the quartic is flat enough near its minimum that each stencil size still moves x, but 
+        // gains almost nothing
+        let flat   = |x: f64, _h: f64| (x - 1.0).powi(4);
+        let plain  = implicit_filtering_report(flat, 3.0, 0.5, 1e-12, &quiet()).unwrap();
+        let config = FilterConfig{stall_eps: 1e-6, stall_patience: 2, ..quiet()};
+        let report = implicit_filtering_report(flat, 3.0, 0.5, 1e-12, &config).unwrap();
+
+        assert_eq!(report.reason, ConvergenceReason::Stalled);
+        assert!(report.outer_iters < plain.outer_iters);
+        assert!(report.eval_count < plain.eval_count);
+    }
+
+    #[test]
+    fn final_hessian_matches_the_curvature(){
+        // a(x - b)^2 with a = 3, b = 0.5 has a second derivative of 6 everywhere
+        let report = implicit_filtering_report(|x: f64, _h: f64| 3.0*(x - 0.5).powi(2), 2.0, 0.1, 1e-7, &quiet()).unwrap();
+
+        assert!((report.final_hess.unwrap() - 6.0).abs() < 1e-6);
+        assert!(report.final_grad.unwrap().abs() < 1e-6);
+    }
+
+    #[test]
+    fn final_gradient_is_taken_at_the_final_point(){
+        let quadratic = |x: f64, _h: f64| (x - 1.0).powi(2);
+
+        let report = implicit_filtering_report(quadratic, 1.5, 0.1, 1e-7, &quiet()).unwrap();
+        assert!((report.final_grad.unwrap() - 2.0*(report.result.x - 1.0)).abs() < 1e-6);
+
+        // on the lower bound the gradient is that of the constrained minimum, not of the start
+        let config = FilterConfig{lower: 2.0, upper: 5.0, ..quiet()};
+        let report = implicit_filtering_report(quadratic, 4.0, 0.1, 1e-7, &config).unwrap();
+        assert!((report.result.x - 2.0).abs() < 1e-6);
+        assert!((report.final_grad.unwrap() - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn table_follows_the_precision(){
+        let quad = |x: f64, _h: f64| (x - 1.0).powi(2);
+
+        for &precision in &[4, 10]{
+            let config  = FilterConfig{precision, ..FilterConfig::default()};
+            let mut out = String::new();
+            implicit_filtering_fmt(quad, 1.5, 0.1, 1e-7, &config, &mut out).unwrap();
+
+            // the first row is the starting point, of width precision + 3
+            let row = out.lines().find(|line| line.starts_with('+')).unwrap();
+            let x   = row.split('|').next().unwrap();
+
+            assert_eq!(x, format!("+1.{:0<p$}", "5", p = precision));
+            assert_eq!(x.len(), precision + 3);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn table_header_is_written_once_per_stencil_size(){
+        let mut out = Vec::new();
+        let report  = implicit_filtering_to(|x: f64, _h: f64| (x - 1.0).powi(2), 1.5, 0.1, 1e-7, 
+                                            &FilterConfig::default(), &mut out).unwrap();
+        let out     = String::from_utf8(out).unwrap();
+
+        assert_eq!(out.matches("Commencing optimisation routine").count(), report.outer_iters);
+    }
+
+    #[test]
+    fn nan_is_worse_than_any_result(){
+        let good = OptimResult{x: 1.0, mse: 0.5};
+        let bad  = OptimResult{x: 2.0, mse: f64::NAN};
+
+        assert!(good.better_than(&bad));
+        assert!(!bad.better_than(&good));
+        assert!(!bad.better_than(&bad));
+        assert!(!good.better_than(&good));
+
+        let ties = [OptimResult{x: 3.0, mse: 0.5}, good];
+        assert_eq!(min_by_mse(vec![bad, good, OptimResult{x: 0.0, mse: 2.0}]), Some(good));
+        assert_eq!(min_by_mse(ties), Some(ties[0]));
+        assert_eq!(min_by_mse(vec![bad]).map(|result| result.x), Some(2.0));
+        assert_eq!(min_by_mse(Vec::<OptimResult>::new()), None);
+    }
+
+    #[test]
+    fn maximize_finds_the_peak(){
+        let config = FilterConfig{direction: OptimDirection::Maximize, ..quiet()};
+        let report = implicit_filtering_report(|x: f64, _h: f64| 2.0 - (x - 0.5).powi(2), 2.0, 0.1, 1e-7, &config).unwrap();
+
+        assert!((report.result.x - 0.5).abs() < 1e-6);
+        assert!((report.result.mse - 2.0).abs() < 1e-10);
+        assert!(report.converged());
+    }
+
+    #[test]
+    fn max_evals_is_never_exceeded(){
+        let mut calls = 0;
+        let mse       = |x: f64, _h: f64| {calls += 1; assert!(calls <= 25, "called {} times", calls); (x - 1.0).powi(4)};
+        let config    = FilterConfig{max_evals: Some(25), ..quiet()};
+        let report    = implicit_filtering_report(mse, 3.0, 0.5, 1e-12, &config).unwrap();
+
+        assert_eq!(report.reason, ConvergenceReason::EvalBudgetExhausted);
+        assert!(report.eval_count <= 25);
+        assert!(report.result.mse < 16.0);
+    }
+
+    #[test]
+    fn one_sided_difference_recovers_a_kink(){
+        // the slope steepens from -0.04 to -0.15 at the kink, so the central difference is small 
+        // enough to fail against h while the right hand side clearly descends
+        let kink   = |x: f64, _h: f64| if x < 0.0 {1.0 - 0.04*x} else {1.0 - 0.15*x};
+        let centre = OptimResult{x: 0.0, mse: 1.0};
+        let grad   = |allow_one_sided| {
+            let config  = FilterConfig{allow_one_sided, ..quiet()};
+            let mut mse = kink;
+            generate_gradient(&mut mse, &centre, 0.1, None, &config).map(|(grad, _)| grad)
+        };
+
+        assert_eq!(grad(false), None);
+        assert!((grad(true).unwrap() + 0.15).abs() < 1e-12);
+    }
+
+    #[cfg(feature = "approx")]
+    #[test]
+    fn results_compare_approximately(){
+        use approx::{AbsDiffEq, RelativeEq};
+
+        let result = OptimResult{x: 1000.0, mse: 1e-3};
+
+        approx::assert_abs_diff_eq!(result, OptimResult{x: 1000.0 + 1e-7, mse: 1e-3 - 1e-7}, epsilon = 1e-6);
+        approx::assert_relative_eq!(result, OptimResult{x: 1000.001, mse: 1.000001e-3}, max_relative = 1e-5);
+
+        // the tolerance applies to each field
+        assert!(!result.abs_diff_eq(&OptimResult{x: 1000.0, mse: 2e-3}, 1e-6));
+        assert!(!result.abs_diff_eq(&OptimResult{x: 1000.1, mse: 1e-3}, 1e-6));
+        assert!(result.relative_eq(&OptimResult{x: 1000.1, mse: 1e-3}, 1e-6, 1e-3));
+        assert!(!result.relative_eq(&OptimResult{x: 1000.0, mse: 1.1e-3}, 1e-6, 1e-3));
+    }
+
+    #[test]
+    fn stencil_samples_reduce_the_gradient_variance(){
+        let variance = |stencil_samples| {
+            let config  = FilterConfig{stencil_samples, ..quiet()};
+            let mut rng = rng::SplitMix64::new(3);
+            let mut mse = |x: f64, _h: f64| (x - 1.0).powi(2) + rng.uniform(-0.01, 0.01);
+            let centre  = OptimResult{x: 2.0, mse: 1.0};
+
+            let grads: Vec<f64> = (0..200).map(|_| generate_gradient(&mut mse, &centre, 0.1, None, &config).unwrap().0)
+                                          .collect();
+            let mean            = grads.iter().sum::<f64>()/grads.len() as f64;
+
+            grads.iter().map(|g| (g - mean).powi(2)).sum::<f64>()/grads.len() as f64
+        };
+
+        // averaging 8 samples should divide the variance by about 8
+        assert!(variance(8) < variance(1)/4.0);
+    }
+
+    #[test]
+    fn optimize_at_stencil_is_the_first_stencil_size(){
+        let quartic = |x: f64, _h: f64| (x - 1.0).powi(4);
+        let config  = FilterConfig{max_outer_iters: 1, ..quiet()};
+
+        let single = optimize_at_stencil(quartic, 3.0, 0.1, &config).unwrap();
+        let full   = implicit_filtering_with(quartic, 3.0, 0.1, 1e-12, &config).unwrap();
+
+        assert_eq!(single, full);
+        assert!(single.mse < quartic(3.0, 0.1));
+    }
+
+    #[test]
+    fn dyn_objectives_chosen_at_runtime(){
+        let mut calls = 0;
+        let mut objectives: Vec<Box<dyn FnMut(f64, f64) -> f64>> = vec![
+            Box::new(|x, _h| (x - 1.0).powi(2)),
+            Box::new(|x, _h| (x + 2.0).powi(2) + 0.1*(x + 2.0).powi(4)),
+            Box::new(|x: f64, _h| {calls += 1; x.cosh()}),
+        ];
+
+        for (mse, minimiser) in objectives.iter_mut().zip(&[1.0, -2.0, 0.0]){
+            let result = implicit_filtering_dyn(mse.as_mut(), 0.5, 0.1, 1e-7).unwrap();
+
+            assert!((result.x - minimiser).abs() < 1e-6);
+        }
+
+        drop(objectives);
+        assert!(calls > 0);
+    }
+
+    #[cfg(feature = "twofloat")]
+    #[test]
+    fn quadratic_in_extended_precision(){
+        // 1/3 to about 32 digits, which no f64 is within 1e-17 of
+        let third  = TwoFloat::from(1.0)/3.0;
+        let mse    = |x: TwoFloat, _h: TwoFloat| (x - third)*(x - third);
+        let result = implicit_filtering_with(mse, TwoFloat::from(2.0), TwoFloat::from(0.5), TwoFloat::from(1e-30), &quiet())
+                         .unwrap();
+
+        assert!(f64::from(result.x - third).abs() < 1e-20);
+    }
+
+    #[test]
+    fn landscape_scan_spans_the_range(){
+        // (x - 1)² with an added oscillation whose local minima lie away from x = 1
+        let mse  = |x: f64, _h: f64| (x - 1.0)*(x - 1.0) + 0.1*(1.0 - (6.0*(x - 1.0)).cos());
+        let scan = landscape_scan(mse, -2.0..4.0, 61, 0.1);
+
+        assert_eq!(scan.len(), 61);
+        assert_eq!(scan[0].0,  -2.0);
+        assert_eq!(scan[60].0,  4.0);
+        assert!(scan.windows(2).all(|pair| (pair[1].0 - pair[0].0 - 0.1).abs() < 1e-12));
+
+        let lowest = scan.iter().fold(scan[0], |best, &point| if point.1 < best.1 {point} else {best});
+        assert!((lowest.0 - 1.0).abs() < 1e-12);
+        assert!(lowest.1.abs() < 1e-20);
+    }
+
+    #[test]
+    fn nonmonotone_search_crosses_a_shallow_ridge(){
+        // a ridge of height 0.05 between the start at 0 and the descent beyond x = 2
+        let mut ridge = |x: f64, _h: f64| if x < 2.0 {0.05*x*(2.0 - x)} else {2.0 - x};
+        let start     = OptimResult{x: 0.0, mse: 0.0};
+        let recent    = [0.2].iter().copied().collect::<VecDeque<f64>>();
+        let config    = FilterConfig{allow_expansion: false, line_search: LineSearch::NonMonotone{memory: 2}, ..quiet()};
+
+        // every trial step lands on the ridge, above the current point
+        assert!(backtracking_line_search(&mut ridge, &start, 1.0, -1.0, 0.01, &config).is_none());
+
+        // measured from the higher value the search passed through earlier, the first trial is accepted
+        let result = line_search(&mut ridge, &start, &recent, 1.0, -1.0, 0.01, &config).unwrap();
+        assert_eq!(result.x, 1.0);
+        assert_eq!(result.mse, 0.05);
+    }
+
+    #[test]
+    fn resuming_from_a_checkpoint_matches_the_uninterrupted_run(){
+        let mse       = |x: f64, _h: f64| (x - 1.0).powi(4) + 1e-3*(1.0 - (50.0*(x - 1.0)).cos());
+        let config    = quiet();
+        let mut saved = Vec::new();
+        let full      = implicit_filtering_checkpointed(mse, 3.0, 0.5, 1e-10, &config, |state| saved.push(*state))
+                            .unwrap();
+        assert!(full.outer_iters > 3);
+
+        // carry on from the third stencil size as though the run had been interrupted there
+        let third   = saved[2];
+        assert_eq!(third.outer_index, 2);
+        let resumed = resume_from(&third, mse, 1e-10, &config).unwrap();
+
+        assert_eq!(resumed.result.x.to_bits(),   full.result.x.to_bits());
+        assert_eq!(resumed.result.mse.to_bits(), full.result.mse.to_bits());
+        assert_eq!(resumed.reason,      full.reason);
+        assert_eq!(resumed.eval_count,  full.eval_count);
+        assert_eq!(resumed.outer_iters, full.outer_iters);
+        assert_eq!(resumed.final_grad,  full.final_grad);
+        assert_eq!(resumed.final_hess,  full.final_hess);
+    }
+
+    #[test]
+    fn relative_tolerance_suits_large_and_small_scales(){
+        // the same objective at each scale, with a curvature of 3 at the start, so that the runs 
+        // differ only through the tolerance. max_step and min_h would otherwise bound them
+        let base     = FilterConfig{min_h: Some(1e-20), max_step: f64::INFINITY, ..quiet()};
+        let relative = FilterConfig{tol_kind: ToleranceKind::Relative{min_scale: 0.0}, ..base.clone()};
+        let run      = |scale: f64, config: &FilterConfig| {
+                           let mse = move |x: f64, _h: f64| ((x - scale).powi(2)/scale).powi(2);
+                           implicit_filtering_report(mse, 1.5*scale, 0.1*scale, 1e-3, config).unwrap()
+                       };
+
+        // near 1e6 no step is smaller than an absolute 1e-3, and near 1e-6 every step is
+        let large = run(1e6, &base);
+        let small = run(1e-6, &base);
+        assert_eq!(large.reason, ConvergenceReason::MaxOuterIterations);
+        assert_eq!(small.reason, ConvergenceReason::ToleranceReached);
+        assert_eq!(small.outer_iters, 1);
+        assert!((small.result.x/1e-6 - 1.0).abs() > 0.1);
+
+        // relative to x, both runs stop at the same point of the schedule with the same relative error
+        let large = run(1e6, &relative);
+        let small = run(1e-6, &relative);
+        for (report, scale) in [(large, 1e6), (small, 1e-6)].iter(){
+            assert_eq!(report.reason, ConvergenceReason::ToleranceReached);
+            assert!((report.result.x/scale - 1.0).abs() < 1e-2);
+        }
+        assert_eq!(large.outer_iters, small.outer_iters);
+        assert_eq!(large.eval_count,  small.eval_count);
+    }
+
+    #[test]
+    fn long_schedules_never_evaluate_degenerate_sizes(){
+        let config   = FilterConfig{max_outer_iters: 10_000, max_inner_iters: 100, max_line_search_iters: 10_000,
+                                    min_h: Some(f64::MIN_POSITIVE), ..quiet()};
+        let mut seen = Vec::new();
+        let mse      = |x: f64, h: f64| {seen.push((x, h));
+                                         (x - 1.0).powi(2) + 1e-3*(1.0 - (1e4*x).cos())};
+        let report   = implicit_filtering_report(mse, 3.0, 0.5, 0.0, &config).unwrap();
+
+        // the schedule ran down to the smallest positive f64, and ended there rather than at the 
+        // limit on stencil sizes
+        assert!(report.outer_iters > 500 && report.outer_iters < 10_000);
+        assert!(report.result.x.is_finite());
+        assert!(seen.iter().all(|&(x, h)| x.is_finite() && h.is_finite() && h > 0.0));
+    }
+
+    #[test]
+    fn path_runs_from_near_the_start_to_the_result(){
+        let (result, path) = implicit_filtering_path(|x: f64, _h: f64| (x - 1.0).powi(4), 3.0, 0.5, 1e-6);
+
+        assert!(!path.is_empty());
+        assert_eq!(*path.last().unwrap(), result.x);
+        assert!((path[0] - 3.0).abs() < 2.0);
+        assert!(path.windows(2).all(|pair| (pair[1] - 1.0).abs() < (pair[0] - 1.0).abs()));
+
+        // a run that can't begin has no path
+        let (failed, path) = implicit_filtering_path(|_x: f64, _h: f64| f64::NAN, 3.0, 0.5, 1e-6);
+        assert_eq!(failed.x, 3.0);
+        assert!(path.is_empty());
+    }
+
+    #[test]
+    fn step_floor_moves_on_to_the_next_stencil_sooner(){
+        // steepest descent overshoots the steep quadratic, so only short steps decrease it
+        let steep  = |x: f64, _h: f64| 10.0*(x - 1.0).powi(2);
+        let levels = |min_step_frac: f64| {
+                         let config     = FilterConfig{min_step_frac, hess_floor: f64::INFINITY, ..quiet()};
+                         let mut mse    = steep;
+                         let mut levels = Vec::<(f64, usize)>::new();
+                         let report     = filter(&mut mse, 1.5, 0.5, 1e-6, &config, &mut |record| {
+                                              match levels.last_mut(){
+                                                  Some((h, count)) if *h == record.h => *count += 1,
+                                                  _                                  => levels.push((record.h, 1)),
+                                              }
+                                              ControlFlow::Continue
+                                          }).unwrap();
+                         (levels, report.eval_count)
+                     };
+
+        let (creeping, creeping_evals) = levels(0.0);
+        let (floored,  floored_evals)  = levels(0.1);
+
+        // from the third stencil size on, the floored search gives up on the first short step
+        assert!(creeping[2..].iter().all(|&(_, count)| count >= 4));
+        assert!(floored[2..].iter().all(|&(_, count)| count == 1));
+        assert!(floored_evals < creeping_evals);
+    }
+
+    #[test]
+    fn curvature_of_a_scaled_quadratic(){
+        let result = OptimResult{x: 1.5, mse: 0.0};
+        assert_eq!(result.distance_to(1.0), 0.5);
+        assert_eq!(result.distance_to(2.0), 0.5);
+
+        for &(a, b) in &[(1.0, 0.0), (3.0, 1.5), (0.25, -2.0)]{
+            let mse = move |x: f64, _h: f64| a*(x - b).powi(2);
+            assert!((result.curvature(mse, 1e-3) - 2.0*a).abs() < 1e-6*a);
+        }
+    }
+
+    #[test]
+    fn non_finite_cells_keep_the_table_aligned(){
+        let config  = FilterConfig::default();
+        let mut out = String::new();
+        print_table_header(&config, &mut Diagnostics::Writer(&mut out), 0.1, 1.5);
+        print_table_row(&config, &mut Diagnostics::Writer(&mut out), &OptimResult{x: 1.5, mse: 0.25}, Some(1.0));
+        print_table_row(&config, &mut Diagnostics::Writer(&mut out), &OptimResult{x: f64::NAN, mse: f64::INFINITY}, 
+                        Some(f64::NEG_INFINITY));
+
+        // the column separators of every row fall where the heading's do
+        let separators = |line: &str| line.char_indices().filter(|&(_, c)| c == '|').map(|(i, _)| i).collect::<Vec<_>>();
+        let lines      = out.lines().collect::<Vec<_>>();
+        let rows       = &lines[lines.len() - 2..];
+        let heading    = lines.iter().find(|line| line.contains("MSE")).unwrap();
+
+        for row in rows{
+            assert_eq!(row.chars().count(), heading.chars().count());
+        }
+        assert_eq!(separators(rows[0]), separators(rows[1]));
+        assert_eq!(rows[1], format!("{0: ^13}|{0: ^18}|{0: ^19}|", "-".repeat(10)));
+    }
+
+    #[test]
+    fn seeded_hessian_saves_evaluations_on_fine_stencils(){
+        // the ripple swamps the second difference once h is small, making the curvature estimates 
+        // useless, while the first difference still finds the way down
+        let rippled = |x: f64, _h: f64| (x - 1.0).powi(2) + 1e-9*(1e7*x).sin();
+        let cold    = implicit_filtering_report(rippled, 3.0, 0.5, 1e-7, &quiet()).unwrap();
+        let seeded  = implicit_filtering_report(rippled, 3.0, 0.5, 1e-7, &FilterConfig{seed_hessian: true, ..quiet()})
+                          .unwrap();
+
+        assert_eq!(seeded.reason, ConvergenceReason::ToleranceReached);
+        assert!((seeded.result.x - 1.0).abs() < 1e-4);
+        assert!(seeded.eval_count < cold.eval_count);
+    }
+
+    #[test]
+    fn golden_section_finds_the_lowest_step(){
+        // a staircase of steps 0.25 wide, which every stencil sees as flat
+        let stairs = |x: f64, _h: f64| (4.0*(x - 1.0).abs()).floor();
+
+        let failed = implicit_filtering_report(stairs, 3.1, 0.01, 1e-7, &quiet());
+        assert_eq!(failed.unwrap_err(), OptimError::StencilFailedEverywhere);
+
+        let config = FilterConfig{fallback: Fallback::GoldenSection{a: -4.0, b: 5.0}, ..quiet()};
+        let report = implicit_filtering_report(stairs, 3.1, 0.01, 1e-7, &config).unwrap();
+        assert_eq!(report.reason, ConvergenceReason::Fallback);
+        assert_eq!(report.result.mse, 0.0);
+        assert!((report.result.x - 1.0).abs() < 0.25);
+    }
+
+    #[test]
+    fn std_error_shrinks_as_the_minimum_sharpens(){
+        // the negative log likelihood of a normal observation of x with standard deviation sigma
+        let errors = [2.0, 0.5, 0.1].iter().map(|&sigma| {
+                         let mse    = move |x: f64, _h: f64| (x - 1.0).powi(2)/(2.0*sigma*sigma);
+                         let result = implicit_filtering_with(mse, 1.5, 0.1, 1e-7, &quiet()).unwrap();
+                         let error  = result.approx_std_error(mse, 1e-3).unwrap();
+
+                         assert!((error - sigma).abs() < 1e-4*sigma);
+                         error
+                     }).collect::<Vec<_>>();
+        assert!(errors.windows(2).all(|pair| pair[1] < pair[0]));
+
+        // there is no standard error at a maximum, or on a plateau
+        let peak = OptimResult{x: 0.0, mse: 0.0};
+        assert_eq!(peak.approx_std_error(|x: f64, _h: f64| -x*x, 1e-3), None);
+        assert_eq!(peak.approx_std_error(|_x: f64, _h: f64| 1.0, 1e-3), None);
+    }
+
+    #[test]
+    fn trust_region_takes_the_newton_step_on_a_quadratic(){
+        let records = |step_strategy: StepStrategy| {
+                          let config      = FilterConfig{step_strategy, ..quiet()};
+                          let mut history = Vec::new();
+                          let mut quad    = |x: f64, _h: f64| 3.0*(x - 1.0).powi(2);
+                          filter(&mut quad, 4.0, 0.5, 1e-7, &config, &mut |record| {history.push(*record); ControlFlow::Continue})
+                              .unwrap();
+                          history
+                      };
+
+        let wide   = records(StepStrategy::TrustRegion{init_radius: 10.0});
+        let narrow = records(StepStrategy::TrustRegion{init_radius: 0.1});
+        let search = records(StepStrategy::LineSearch);
+
+        // the central stencil is exact for a quadratic, so the whole way to the minimum is one step
+        assert!(wide[0].line_search_ok);
+        assert!((wide[1].x - 1.0).abs() < 1e-12);
+        assert!(wide.len() <= search.len());
+        assert!(wide.len() <  narrow.len());
+    }
+
+    #[test]
+    fn uneven_stencil_differentiates_a_cubic(){
+        let cubic        = |x: f64, _h: f64| x.powi(3) - 2.0*x;
+        let mut mse      = cubic;
+        let (x, left)    = (0.7, 0.01);
+        let right        = 0.03;
+        let centre       = OptimResult{x, mse: cubic(x, 0.0)};
+        let (grad, hess) = generate_gradient(&mut mse, &centre, 0.02, Some((left, right)), &quiet()).unwrap();
+
+        // the quadratic through uneven points has gradient error left*right*f'''/6 and Hessian 
+        // error (right - left)*f'''/3, where f''' = 6 for this cubic
+        let (exact_grad, exact_hess) = (3.0*x*x - 2.0, 6.0*x);
+        assert!((grad - exact_grad - left*right).abs() < 1e-10);
+        assert!((hess.unwrap() - exact_hess - 2.0*(right - left)).abs() < 1e-8);
+
+        // much better than the slope of the chord, whose error is of order right - left
+        let chord = (cubic(x + right, 0.0) - cubic(x - left, 0.0))/(left + right);
+        assert!((grad - exact_grad).abs() < 0.1*(chord - exact_grad).abs());
+    }
+
+    #[test]
+    fn converged_only_when_a_tolerance_was_met(){
+        let quad   = |x: f64, _h: f64| (x - 1.0).powi(2);
+        let report = implicit_filtering_report(quad, 1.5, 0.1, 1e-7, &quiet()).unwrap();
+
+        assert!(report.converged());
+        assert_eq!(report.outer_iterations_used(), report.outer_iters);
+
+        let quartic = |x: f64, _h: f64| (x - 1.0).powi(4);
+        let config  = FilterConfig{max_outer_iters: 4, ..quiet()};
+        let report  = implicit_filtering_report(quartic, 2.0, 0.5, 1e-12, &config).unwrap();
+
+        assert_eq!(report.reason, ConvergenceReason::MaxOuterIterations);
+        assert!(!report.converged());
+        assert_eq!(report.outer_iterations_used(), 4);
+
+        let config = FilterConfig{max_evals: Some(10), ..quiet()};
+        let report = implicit_filtering_report(quad, 1.5, 0.1, 1e-12, &config).unwrap();
+
+        assert_eq!(report.reason, ConvergenceReason::EvalBudgetExhausted);
+        assert!(!report.converged());
+    }
+}
+
+
 
 
 
@@ -1,23 +1,51 @@
-mod euler;
-use euler::rk2;
+use implicit_filtering::euler::{mse_against_analytic, rk2_from, rk4_from};
 
-const BETA       : f64 = 1.0;
-const FINAL_TIME : f64 = 5.0; 
+const BETA          : f64 = 1.0;
+const INITIAL_TIME  : f64 = 0.0;
+const INITIAL_VALUE : f64 = 1.0;
+const FINAL_TIME    : f64 = 5.0; 
 
-fn main() {
-    
-    let result = implicit_filtering::implicit_filtering(get_mse_rk2, 1.5, 0.1, 0.0000001);
+// print the optimiser's diagnostics to stderr, setting warnings and table headings apart from the
+// table rows with a blank line
+struct StderrLogger;
 
-    println!("\nFinal Result: β = {0: <+12.10}, MSE = {1: <+12.10}", result.x, result.mse);
-}
+impl log::Log for StderrLogger{
+    fn enabled(&self, _metadata: &log::Metadata) -> bool{
+        true
+    }
 
-fn get_mse_rk2(x: f64, h:f64) -> f64{
-    let true_val = (BETA*FINAL_TIME).exp();
-    let estimated_val = rk2(x,h, FINAL_TIME);
+    fn log(&self, record: &log::Record){
+        let msg = record.args().to_string();
 
-    let error = true_val - estimated_val;
+        if record.level() <= log::Level::Warn || msg.contains('\n'){
+            eprintln!("\n{}", msg);
+        } else {
+            eprintln!("{}", msg);
+        }
+    }
 
-    error.powi(2)
+    fn flush(&self){}
 }
 
+static LOGGER: StderrLogger = StderrLogger;
 
+fn main() {
+    if log::set_logger(&LOGGER).is_ok(){
+        log::set_max_level(log::LevelFilter::Debug);
+    }
+    
+    let exact = |t: f64| INITIAL_VALUE*(BETA*(t - INITIAL_TIME)).exp();
+
+    // pass rk4 on the command line to fit the fourth order method instead
+    let get_mse: Box<dyn Fn(f64, f64) -> f64> = match std::env::args().nth(1).as_deref(){
+        Some("rk4") => Box::new(mse_against_analytic(|x, h, t| rk4_from(x, h, INITIAL_TIME, INITIAL_VALUE, t), 
+                                                     exact, FINAL_TIME)),
+        _           => Box::new(mse_against_analytic(|x, h, t| rk2_from(x, h, INITIAL_TIME, INITIAL_VALUE, t), 
+                                                     exact, FINAL_TIME)),
+    };
+
+    match implicit_filtering::implicit_filtering(get_mse, 1.5, 0.1, 0.0000001){
+        Ok(result) => println!("\nFinal Result: {}", result),
+        Err(err)   => println!("\nOptimisation failed: {}", err),
+    }
+}
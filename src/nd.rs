@@ -0,0 +1,452 @@
+
+// Implicit filtering for objectives of a parameter vector. The gradient and the diagonal of the
+// Hessian are estimated coordinate by coordinate with central differences, and each step is a
+// diagonal quasi-Newton step in the full vector. With StencilKind::Compass, a failed step falls 
+// back to moving to the best point of the stencil itself. The bounds, the gradient scheme and 
+// the stencil options, the line search and step strategy options, the fallback, the gradient, 
+// objective and stall tolerances, the time limit and the evaluation budget in FilterConfig only 
+// apply to the scalar optimiser, and a config that changes any of them is rejected
+
+#[cfg(feature = "ndarray")]
+use ndarray::{Array1, ArrayView1};
+
+use crate::{power, table_cell, FilterConfig, OptimDirection, OptimError, SearchFailure, StencilKind};
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OptimResultND{
+   pub x: Vec<f64>,
+   pub mse: f64,
+}
+
+fn norm(v: &[f64]) -> f64{
+    v.iter().map(|vi| vi*vi).sum::<f64>().sqrt()
+}
+
+fn dot(u: &[f64], v: &[f64]) -> f64{
+    u.iter().zip(v).map(|(ui, vi)| ui*vi).sum()
+}
+
+// x + a*p
+fn step(x: &[f64], a: f64, p: &[f64]) -> Vec<f64>{
+    x.iter().zip(p).map(|(xi, pi)| xi + a*pi).collect()
+}
+
+// The result of a run of the multidimensional optimiser, together with the work it took
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FilterReportND{
+    pub result: OptimResultND,
+    // the number of times the objective was called
+    pub eval_count: usize,
+}
+
+// the objective at x ± h*e_i for each coordinate i, as (right, left) pairs
+fn sample_stencil<F>(mse: &mut F, x: &[f64], h: f64) -> Vec<(f64, f64)>
+    where F: FnMut(&[f64], f64) -> f64
+{
+    let mut point = x.to_vec();
+
+    (0..x.len()).map(|i| {
+        point[i] = x[i] + h;
+        let mse_right = mse(&point, h);
+        point[i] = x[i] - h;
+        let mse_left  = mse(&point, h);
+        point[i] = x[i];
+
+        (mse_right, mse_left)
+    }).collect()
+}
+
+// estimate the gradient and the Hessian diagonal from the stencil. This fails if no coordinate 
+// offers descent, if the gradient is small relative to the stepsize, or if the objective isn't 
+// finite on the stencil
+fn generate_gradient(stencil: &[(f64, f64)], result: &OptimResultND, h: f64, config: &FilterConfig) 
+    -> Option<(Vec<f64>, Vec<f64>)>
+{
+    let n = result.x.len();
+
+    let mut grad = vec![0.0; n];
+    let mut hess = vec![0.0; n];
+    let mut no_descent_direction = true;
+
+    for (i, &(mse_right, mse_left)) in stencil.iter().enumerate(){
+        // a stencil that runs into NaN or infinite values can't be differenced
+        if !(mse_right.is_finite() && mse_left.is_finite()){
+            return None;
+        }
+
+        grad[i] = (mse_right - mse_left)/(2.0*h);
+        hess[i] = (mse_right + mse_left - 2.0*result.mse)/(h*h);
+
+        if mse_right < result.mse || mse_left < result.mse{
+            no_descent_direction = false;
+        }
+    }
+
+    if no_descent_direction || norm(&grad) <= config.grad_h_ratio*h{
+        None
+    } else {
+        Some((grad, hess))
+    }
+}
+
+// backtracking along p until the Armijo condition holds
+fn backtracking_line_search<F>(mse: &mut F, start: &OptimResultND, p: &[f64], grad: &[f64], h: f64, 
+                               config: &FilterConfig) -> Option<OptimResultND>
+    where F: FnMut(&[f64], f64) -> f64
+{
+    let slope = dot(p, grad);
+
+    for i in 0..config.max_line_search_iters{
+        let a = power(config.line_search_reduction, i);
+
+        if a == 0.0 || a < config.min_step_frac{
+            return None;
+        }
+
+        let x_new   = step(&start.x, a, p);
+        let mse_new = mse(&x_new, h);
+
+        if mse_new.is_finite() && mse_new - start.mse <= config.armijo_constant*a*slope{
+            return Some(OptimResultND{x: x_new, mse: mse_new});
+        }
+    }
+
+    None
+}
+
+// the best point of the stencil, if it improves on the centre
+fn compass_move(stencil: &[(f64, f64)], result: &OptimResultND, h: f64) -> Option<OptimResultND>{
+    let mut best: Option<(usize, f64, f64)> = None;
+
+    for (i, &(mse_right, mse_left)) in stencil.iter().enumerate(){
+        for &(offset, value) in &[(h, mse_right), (-h, mse_left)]{
+            let best_mse = best.map_or(result.mse, |(_, _, best_mse)| best_mse);
+
+            if value.is_finite() && value < best_mse{
+                best = Some((i, offset, value));
+            }
+        }
+    }
+
+    best.map(|(i, offset, value)| {
+        let mut x = result.x.clone();
+        x[i] += offset;
+        OptimResultND{x, mse: value}
+    })
+}
+
+// the inner search at a fixed stencil size, returning why it made no progress if it didn't
+fn grad_search<F>(mse: &mut F, x: &[f64], h: f64, config: &FilterConfig) -> Result<OptimResultND, SearchFailure>
+    where F: FnMut(&[f64], f64) -> f64
+{
+    let old_result = OptimResultND{x: x.to_vec(), mse: mse(x, h)};
+    let mut current_result = old_result.clone();
+    let mut stencil_failed = false;
+
+    // with the compass stencil, fall back to the best stencil point whenever the quasi-Newton step 
+    // can't be taken, and only give up on this stencil size when none of them improves
+    let compass = |stencil: &[(f64, f64)], current: &OptimResultND| match config.stencil_kind{
+        StencilKind::Compass          => compass_move(stencil, current, h),
+        StencilKind::FiniteDifference => None,
+    };
+
+    for _i in 0..config.max_inner_iters{
+        let stencil = sample_stencil(mse, &current_result.x, h);
+
+        let (grad, hess) = match generate_gradient(&stencil, &current_result, h, config){
+            Some(gh) => gh,
+            None     => match compass(&stencil, &current_result){
+                            Some(result) => {current_result = result; continue},
+                            None         => {stencil_failed = true; break},
+                        },
+        };
+
+        // diagonal quasi-Newton step, using steepest descent in any coordinate whose curvature is below
+        // hess_floor
+        let mut p: Vec<f64> = grad.iter().zip(&hess)
+                                  .map(|(&g, &c)| if c > config.hess_floor {-g/c} else {-g})
+                                  .collect();
+
+        if dot(&p, &grad) >= 0.0{
+            p = grad.iter().map(|g| -g).collect();
+        }
+
+        let p_norm = norm(&p);
+        if p_norm > config.max_step{
+            p.iter_mut().for_each(|pi| *pi *= config.max_step/p_norm);
+        }
+
+        if config.verbose{
+            log::debug!("{:?}|{}|{}|", current_result.x, 
+                        table_cell(current_result.mse, config.precision + 8, config.precision, false),
+                        table_cell(norm(&grad), config.precision + 9, config.precision, false));
+        }
+
+        match backtracking_line_search(mse, &current_result, &p, &grad, h, config){
+            Some(result) => current_result = result,
+            None         => match compass(&stencil, &current_result){
+                                Some(result) => current_result = result,
+                                None         => break,
+                            },
+        }
+    }
+
+    if current_result.mse < old_result.mse{
+        Ok(current_result)
+    } else if stencil_failed {
+        Err(SearchFailure::Stencil)
+    } else {
+        Err(SearchFailure::NoImprovement)
+    }
+}
+
+// refuse a config that changes a field only the scalar optimiser uses, rather than ignore it
+fn check_scalar_only(config: &FilterConfig) -> Result<(), OptimError>{
+    let default = FilterConfig::default();
+
+    let changed = config.lower           != default.lower
+               || config.upper           != default.upper
+               || config.gradient_scheme != default.gradient_scheme
+               || config.allow_one_sided != default.allow_one_sided
+               || config.stencil_offsets != default.stencil_offsets
+               || config.stencil_samples != default.stencil_samples
+               || config.line_search     != default.line_search
+               || config.step_strategy   != default.step_strategy
+               || config.allow_expansion != default.allow_expansion
+               || config.seed_hessian    != default.seed_hessian
+               || config.fallback        != default.fallback
+               || config.grad_tol        != default.grad_tol
+               || config.f_tol           != default.f_tol
+               || config.stall_eps       != default.stall_eps
+               || config.stall_patience  != default.stall_patience
+               || config.time_limit      != default.time_limit
+               || config.max_evals       != default.max_evals;
+
+    if changed {
+        Err(OptimError::InvalidConfig("the config sets an option that only the scalar optimiser supports"))
+    } else {
+        Ok(())
+    }
+}
+
+// Minimise mse(x, h) over the vector x, starting from x0 with an initial stencil size of h0
+pub fn implicit_filtering_nd<F>(mse: F, x0: &[f64], h0: f64, tol: f64) -> Result<OptimResultND, OptimError>
+    where F: FnMut(&[f64], f64) -> f64
+{
+    implicit_filtering_nd_with(mse, x0, h0, tol, &FilterConfig::default())
+}
+
+// implicit_filtering_nd with user supplied algorithm constants
+pub fn implicit_filtering_nd_with<F>(mse: F, x0: &[f64], h0: f64, tol: f64, config: &FilterConfig) 
+    -> Result<OptimResultND, OptimError>
+    where F: FnMut(&[f64], f64) -> f64
+{
+    implicit_filtering_nd_report(mse, x0, h0, tol, config).map(|report| report.result)
+}
+
+// implicit_filtering_nd_with, additionally reporting the number of objective evaluations
+pub fn implicit_filtering_nd_report<F>(mut mse: F, x0: &[f64], h0: f64, tol: f64, config: &FilterConfig) 
+    -> Result<FilterReportND, OptimError>
+    where F: FnMut(&[f64], f64) -> f64
+{
+    config.validate()?;
+    check_scalar_only(config)?;
+
+    if !(h0.is_finite() && h0 > 0.0){
+        return Err(OptimError::InvalidConfig("h0 must be positive and finite"));
+    }
+
+    // a maximum is found by minimising the negated objective
+    let sign = if config.direction == OptimDirection::Maximize {-1.0} else {1.0};
+
+    let mut eval_count = 0;
+    let mut mse = |x: &[f64], h: f64| {eval_count += 1; sign*mse(x, h)};
+
+    let mut old_result = OptimResultND{x: x0.to_vec(), mse: mse(x0, h0)};
+
+    if !old_result.mse.is_finite(){
+        return Err(OptimError::NonFiniteObjective);
+    }
+
+    let mut improved       = false;
+    let mut gradient_found = false;
+
+    for i in config.start_level..config.schedule.len(config.max_outer_iters){
+        let h = config.schedule.stencil_size(h0, i);
+
+        if !h.is_finite(){
+            break;
+        }
+
+        // as in the scalar optimiser, stop rather than difference over a stencil smaller than min_h
+        let min_h = config.min_h::<f64>();
+        let h     = if h >= min_h {h} else if i == config.start_level {min_h} else {break};
+
+        let new_result = match grad_search(&mut mse, &old_result.x, h, config){
+            Ok(result)                        => result,
+            Err(SearchFailure::NoImprovement) => {gradient_found = true; continue},
+            Err(_)                            => continue,
+        };
+
+        let diff: Vec<f64> = old_result.x.iter().zip(&new_result.x).map(|(a, b)| a - b).collect();
+        let threshold      = config.tol_kind.threshold(tol, norm(&old_result.x));
+
+        old_result     = new_result;
+        improved       = true;
+        gradient_found = true;
+
+        if norm(&diff) <= threshold{
+            break;
+        }
+    }
+
+    old_result.mse *= sign;
+
+    if improved {
+        Ok(FilterReportND{result: old_result, eval_count})
+    } else if gradient_found {
+        Err(OptimError::NoImprovement)
+    } else {
+        Err(OptimError::StencilFailedEverywhere)
+    }
+}
+
+// The result of implicit_filtering_ndarray
+#[cfg(feature = "ndarray")]
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct OptimResultArray{
+   pub x: Array1<f64>,
+   pub mse: f64,
+}
+
+// implicit_filtering_nd for code built on ndarray. Each point is shown to the objective as a view
+// of the optimiser's own vector, so the points aren't copied for the objective, and the final 
+// vector is moved into the resulting Array1. x0 is copied once into the optimiser's vector, and a
+// view that isn't contiguous is gathered into a Vec first
+#[cfg(feature = "ndarray")]
+pub fn implicit_filtering_ndarray<F>(mse: F, x0: ArrayView1<f64>, h0: f64, tol: f64) -> Result<OptimResultArray, OptimError>
+    where F: FnMut(ArrayView1<f64>, f64) -> f64
+{
+    implicit_filtering_ndarray_with(mse, x0, h0, tol, &FilterConfig::default())
+}
+
+// implicit_filtering_ndarray with user supplied algorithm constants
+#[cfg(feature = "ndarray")]
+pub fn implicit_filtering_ndarray_with<F>(mut mse: F, x0: ArrayView1<f64>, h0: f64, tol: f64, config: &FilterConfig) 
+    -> Result<OptimResultArray, OptimError>
+    where F: FnMut(ArrayView1<f64>, f64) -> f64
+{
+    let gathered;
+    let x0 = match x0.as_slice(){
+                 Some(x0) => x0,
+                 None     => {gathered = x0.to_vec(); &gathered},
+             };
+
+    let result = implicit_filtering_nd_with(|x: &[f64], h| mse(ArrayView1::from(x), h), x0, h0, tol, config)?;
+
+    Ok(OptimResultArray{x: Array1::from(result.x), mse: result.mse})
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    // the default configuration without the diagnostic table
+    fn quiet() -> FilterConfig{
+        FilterConfig{verbose: false, ..FilterConfig::default()}
+    }
+
+    fn bowl(x: &[f64], _h: f64) -> f64{
+        (x[0] - 1.0).powi(2) + 2.0*(x[1] + 0.5).powi(2)
+    }
+
+    #[test]
+    fn bowl_reaches_its_minimum(){
+        let result = implicit_filtering_nd_with(bowl, &[0.0, 0.0], 0.1, 1e-7, &quiet()).unwrap();
+
+        assert!((result.x[0] - 1.0).abs() < 1e-5);
+        assert!((result.x[1] + 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn flat_objective_is_a_stencil_failure(){
+        let result = implicit_filtering_nd_with(|_x: &[f64], _h: f64| 1.0, &[0.0, 0.0], 0.1, 1e-7, &quiet());
+
+        assert_eq!(result, Err(OptimError::StencilFailedEverywhere));
+    }
+
+    #[test]
+    fn scalar_only_options_are_rejected(){
+        let rejected = |config: FilterConfig| {
+            implicit_filtering_nd_with(bowl, &[0.0, 0.0], 0.1, 1e-7, &config) 
+                == Err(OptimError::InvalidConfig("the config sets an option that only the scalar optimiser supports"))
+        };
+
+        assert!(rejected(FilterConfig{lower: -1.0, ..quiet()}));
+        assert!(rejected(FilterConfig{gradient_scheme: crate::GradientScheme::Forward, ..quiet()}));
+        assert!(rejected(FilterConfig{f_tol: 1e-6, ..quiet()}));
+        assert!(rejected(FilterConfig{max_evals: Some(100), ..quiet()}));
+        assert!(!rejected(FilterConfig{max_step: 1.0, ..quiet()}));
+    }
+
+    #[test]
+    fn start_level_skips_the_coarse_stencils(){
+        let config    = FilterConfig{start_level: 2, ..quiet()};
+        let mut sizes = Vec::new();
+        let mse       = |x: &[f64], h: f64| {sizes.push(h); bowl(x, h)};
+
+        implicit_filtering_nd_with(mse, &[0.0, 0.0], 0.1, 1e-7, &config).unwrap();
+
+        // the first call is at the starting point, and the search starts on the third stencil size
+        let first: f64 = config.schedule.stencil_size(0.1, 2);
+        assert_eq!(sizes[1], first);
+        assert!(sizes[1..].iter().all(|&h| h <= first));
+    }
+
+    #[test]
+    fn compass_moves_on_a_piecewise_objective(){
+        // a staircase, flat on each step of width 0.05, whose steps mislead the central differences
+        let stairs = |x: &[f64], _h: f64| x.iter().map(|xi| (xi/0.05).floor().abs()).sum::<f64>();
+        let run    = |stencil_kind| {
+            let config = FilterConfig{stencil_kind, ..quiet()};
+            implicit_filtering_nd_report(stairs, &[1.0, -0.8], 0.1, 1e-7, &config)
+        };
+
+        let gradient = run(StencilKind::FiniteDifference).unwrap();
+        let compass  = run(StencilKind::Compass).unwrap();
+
+        // the bottom step is [0, 0.05) in each coordinate
+        assert_eq!(compass.result.mse, 0.0);
+        assert!(gradient.result.mse > 0.0);
+        assert!(compass.eval_count > 0);
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn ndarray_quadratic_in_three_dimensions(){
+        let centre = ndarray::arr1(&[1.0, -2.0, 0.5]);
+        let scales = ndarray::arr1(&[1.0, 3.0, 0.5]);
+        let mse    = |x: ArrayView1<f64>, _h: f64| ((&x - &centre).mapv(|d| d*d)*&scales).sum();
+        let result = implicit_filtering_ndarray_with(mse, ndarray::arr1(&[0.0, 0.0, 0.0]).view(), 0.1, 1e-7, &quiet())
+                         .unwrap();
+
+        assert_eq!(result.x.len(), 3);
+        assert!((&result.x - &centre).iter().all(|d| d.abs() < 1e-5));
+        assert!(result.mse < 1e-9);
+
+        // every other element of a longer array is a view that isn't contiguous
+        let strided = ndarray::arr1(&[0.0, 9.0, 0.0, 9.0, 0.0]);
+        let again   = implicit_filtering_ndarray_with(mse, strided.slice(ndarray::s![..;2]), 0.1, 1e-7, &quiet())
+                          .unwrap();
+
+        assert_eq!(again, result);
+    }
+}
@@ -0,0 +1,641 @@
+
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use num_complex::Complex;
+use num_traits::Float;
+
+// The optimiser calls the objective through the Objective trait rather than directly. Any closure
+// or function taking (x, h) is an Objective, but a problem can also implement the trait on its own
+// type to carry whatever configuration it needs. Internally, a wrapper can change how the stencil
+// points are evaluated (concurrently, for example) or keep statistics about the calls.
+//
+// mse takes &mut self, and the optimisers take the objective by value, so that a closure may
+// update what it captures and a wrapper may count its calls. An objective that should outlive 
+// the run is lent to it with by_ref, as an iterator would be, e.g.
+//
+//     let mut problem = MyProblem::new();
+//     implicit_filtering(problem.by_ref(), 1.5, 0.1, 1e-7)?;
+//     problem.mse(1.0, 0.1);
+
+pub trait Objective<T: Copy = f64>{
+    // the objective at x, smoothed at stepsize h
+    fn mse(&mut self, x: T, h: T) -> T;
+
+    // evaluate at two points with the same stepsize, as the stencils do
+    fn mse_pair(&mut self, x1: T, x2: T, h: T) -> (T, T){
+        (self.mse(x1, h), self.mse(x2, h))
+    }
+
+    // evaluate at four points with the same stepsize, as the wider stencils do
+    fn mse_quad(&mut self, x: [T; 4], h: T) -> [T; 4]{
+        let (v1, v2) = self.mse_pair(x[0], x[1], h);
+        let (v3, v4) = self.mse_pair(x[2], x[3], h);
+
+        [v1, v2, v3, v4]
+    }
+
+    // the derivative of the objective at x, if it is known analytically. When this returns Some, 
+    // it is used in place of the finite difference gradient, although the stencil is still used to
+    // estimate the Hessian
+    fn grad(&mut self, _x: T, _h: T) -> Option<T>{
+        None
+    }
+
+    // true if each call to grad evaluates the objective, as the complex step gradient does, so 
+    // that it counts towards eval_count and max_evals
+    fn grad_calls_objective(&self) -> bool{
+        false
+    }
+
+    // the work the objective has done so far, in whatever unit suits it, such as the number of 
+    // steps taken by an ODE solver it runs. The work done during a run is reported as 
+    // FilterReport::objective_work. None if the objective doesn't keep count
+    fn work(&self) -> Option<usize>{
+        None
+    }
+
+    // an Objective that forwards every call to this one, so that it can be lent to a run
+    fn by_ref(&mut self) -> ByRef<'_, Self> 
+        where Self: Sized
+    {
+        ByRef(self)
+    }
+}
+
+impl<T, F> Objective<T> for F
+    where T: Copy, F: FnMut(T, T) -> T
+{
+    fn mse(&mut self, x: T, h: T) -> T{
+        self(x, h)
+    }
+}
+
+// The objective borrowed by Objective::by_ref
+pub struct ByRef<'a, E>(&'a mut E);
+
+impl<T, E> Objective<T> for ByRef<'_, E>
+    where T: Copy, E: Objective<T>
+{
+    fn mse(&mut self, x: T, h: T) -> T{
+        self.0.mse(x, h)
+    }
+
+    fn mse_pair(&mut self, x1: T, x2: T, h: T) -> (T, T){
+        self.0.mse_pair(x1, x2, h)
+    }
+
+    fn mse_quad(&mut self, x: [T; 4], h: T) -> [T; 4]{
+        self.0.mse_quad(x, h)
+    }
+
+    fn grad(&mut self, x: T, h: T) -> Option<T>{
+        self.0.grad(x, h)
+    }
+
+    fn grad_calls_objective(&self) -> bool{
+        self.0.grad_calls_objective()
+    }
+
+    fn work(&self) -> Option<usize>{
+        self.0.work()
+    }
+}
+
+// counts the calls made to the wrapped objective. Once the calls reach the budget, if there is 
+// one, the objective is no longer called and NaN is returned instead
+pub(crate) struct Counting<'a, E>{
+    pub inner: &'a mut E,
+    pub count: usize,
+    pub budget: Option<usize>,
+    // a call was refused because of the budget
+    pub exhausted: bool,
+}
+
+impl<'a, E> Counting<'a, E>{
+    pub fn new(inner: &'a mut E, budget: Option<usize>) -> Counting<'a, E>{
+        Counting{inner, count: 0, budget, exhausted: false}
+    }
+
+    // the number of calls still allowed
+    fn remaining(&self) -> usize{
+        self.budget.map_or(usize::MAX, |budget| budget.saturating_sub(self.count))
+    }
+}
+
+impl<T, E> Objective<T> for Counting<'_, E>
+    where T: Float, E: Objective<T>
+{
+    fn mse(&mut self, x: T, h: T) -> T{
+        if self.remaining() == 0{
+            self.exhausted = true;
+            return T::nan();
+        }
+
+        self.count += 1;
+        self.inner.mse(x, h)
+    }
+
+    fn mse_pair(&mut self, x1: T, x2: T, h: T) -> (T, T){
+        if self.remaining() < 2{
+            return (self.mse(x1, h), self.mse(x2, h));
+        }
+
+        self.count += 2;
+        self.inner.mse_pair(x1, x2, h)
+    }
+
+    fn mse_quad(&mut self, x: [T; 4], h: T) -> [T; 4]{
+        if self.remaining() < 4{
+            let (v1, v2) = self.mse_pair(x[0], x[1], h);
+            let (v3, v4) = self.mse_pair(x[2], x[3], h);
+            return [v1, v2, v3, v4];
+        }
+
+        self.count += 4;
+        self.inner.mse_quad(x, h)
+    }
+
+    fn grad(&mut self, x: T, h: T) -> Option<T>{
+        if self.inner.grad_calls_objective(){
+            if self.remaining() == 0{
+                self.exhausted = true;
+                return None;
+            }
+
+            self.count += 1;
+        }
+
+        self.inner.grad(x, h)
+    }
+
+    fn grad_calls_objective(&self) -> bool{
+        self.inner.grad_calls_objective()
+    }
+
+    fn work(&self) -> Option<usize>{
+        self.inner.work()
+    }
+}
+
+// the mean of samples calls to the wrapped objective at the same point, which reduces the 
+// variance of a noisy objective
+pub(crate) struct Averaged<'a, E>{
+    pub inner: &'a mut E,
+    pub samples: usize,
+}
+
+impl<T, E> Objective<T> for Averaged<'_, E>
+    where T: Float, E: Objective<T>
+{
+    fn mse(&mut self, x: T, h: T) -> T{
+        let total = (0..self.samples).fold(T::zero(), |total, _| total + self.inner.mse(x, h));
+
+        total/T::from(self.samples).unwrap()
+    }
+
+    fn mse_pair(&mut self, x1: T, x2: T, h: T) -> (T, T){
+        let (total1, total2) = (0..self.samples).fold((T::zero(), T::zero()), |(total1, total2), _| {
+            let (v1, v2) = self.inner.mse_pair(x1, x2, h);
+            (total1 + v1, total2 + v2)
+        });
+        let n = T::from(self.samples).unwrap();
+
+        (total1/n, total2/n)
+    }
+
+    fn mse_quad(&mut self, x: [T; 4], h: T) -> [T; 4]{
+        let totals = (0..self.samples).fold([T::zero(); 4], |mut totals, _| {
+            let values = self.inner.mse_quad(x, h);
+            totals.iter_mut().zip(&values).for_each(|(total, &v)| *total = *total + v);
+            totals
+        });
+        let n = T::from(self.samples).unwrap();
+
+        totals.map(|total| total/n)
+    }
+
+    fn grad(&mut self, x: T, h: T) -> Option<T>{
+        self.inner.grad(x, h)
+    }
+
+    fn grad_calls_objective(&self) -> bool{
+        self.inner.grad_calls_objective()
+    }
+
+    fn work(&self) -> Option<usize>{
+        self.inner.work()
+    }
+}
+
+// the negation of the wrapped objective, so that minimising it maximises the objective
+pub(crate) struct Negated<'a, E>(pub &'a mut E);
+
+impl<T, E> Objective<T> for Negated<'_, E>
+    where T: Float, E: Objective<T>
+{
+    fn mse(&mut self, x: T, h: T) -> T{
+        -self.0.mse(x, h)
+    }
+
+    fn mse_pair(&mut self, x1: T, x2: T, h: T) -> (T, T){
+        let (v1, v2) = self.0.mse_pair(x1, x2, h);
+        (-v1, -v2)
+    }
+
+    fn mse_quad(&mut self, x: [T; 4], h: T) -> [T; 4]{
+        self.0.mse_quad(x, h).map(|v| -v)
+    }
+
+    fn grad(&mut self, x: T, h: T) -> Option<T>{
+        self.0.grad(x, h).map(|g| -g)
+    }
+
+    fn grad_calls_objective(&self) -> bool{
+        self.0.grad_calls_objective()
+    }
+
+    fn work(&self) -> Option<usize>{
+        self.0.work()
+    }
+}
+
+// Counts the calls made to the wrapped objective, in the way FilterReport::eval_count does. A 
+// mutable reference to it is an Objective too, so it can be lent to a run and the count read 
+// afterwards, e.g.
+//
+//     let mut counting = CountingObjective::new(mse);
+//     implicit_filtering(&mut counting, 1.5, 0.1, 1e-7)?;
+//     let (mse, calls) = counting.into_parts();
+pub struct CountingObjective<E>{
+    inner : E,
+    count : usize,
+}
+
+impl<E> CountingObjective<E>{
+    pub fn new(inner: E) -> CountingObjective<E>{
+        CountingObjective{inner, count: 0}
+    }
+
+    // the number of calls made so far
+    pub fn count(&self) -> usize{
+        self.count
+    }
+
+    // the wrapped objective and the number of calls made to it
+    pub fn into_parts(self) -> (E, usize){
+        (self.inner, self.count)
+    }
+}
+
+impl<T, E> Objective<T> for CountingObjective<E>
+    where T: Copy, E: Objective<T>
+{
+    fn mse(&mut self, x: T, h: T) -> T{
+        self.count += 1;
+        self.inner.mse(x, h)
+    }
+
+    fn mse_pair(&mut self, x1: T, x2: T, h: T) -> (T, T){
+        self.count += 2;
+        self.inner.mse_pair(x1, x2, h)
+    }
+
+    fn mse_quad(&mut self, x: [T; 4], h: T) -> [T; 4]{
+        self.count += 4;
+        self.inner.mse_quad(x, h)
+    }
+
+    fn grad(&mut self, x: T, h: T) -> Option<T>{
+        if self.inner.grad_calls_objective(){
+            self.count += 1;
+        }
+
+        self.inner.grad(x, h)
+    }
+
+    fn grad_calls_objective(&self) -> bool{
+        self.inner.grad_calls_objective()
+    }
+
+    fn work(&self) -> Option<usize>{
+        self.inner.work()
+    }
+}
+
+impl<T, E> Objective<T> for &mut CountingObjective<E>
+    where T: Copy, E: Objective<T>
+{
+    fn mse(&mut self, x: T, h: T) -> T{
+        (**self).mse(x, h)
+    }
+
+    fn mse_pair(&mut self, x1: T, x2: T, h: T) -> (T, T){
+        (**self).mse_pair(x1, x2, h)
+    }
+
+    fn mse_quad(&mut self, x: [T; 4], h: T) -> [T; 4]{
+        (**self).mse_quad(x, h)
+    }
+
+    fn grad(&mut self, x: T, h: T) -> Option<T>{
+        (**self).grad(x, h)
+    }
+
+    fn grad_calls_objective(&self) -> bool{
+        (**self).grad_calls_objective()
+    }
+
+    fn work(&self) -> Option<usize>{
+        (**self).work()
+    }
+}
+
+// Remembers every value of the wrapped objective, keyed on the exact bit patterns of x and h, so
+// that no point is evaluated twice. As with CountingObjective, a mutable reference to it is also
+// an Objective, so the cache can be examined or reused once a run is over
+#[cfg(feature = "std")]
+pub struct CachedObjective<E, T = f64>{
+    inner : E,
+    // each value is stored along with the x and h it was evaluated at
+    cache : HashMap<(CacheKey, CacheKey), (T, T, T)>,
+}
+
+// the mantissa, exponent and sign of a float, which identify it exactly
+#[cfg(feature = "std")]
+type CacheKey = (u64, i16, i8);
+
+#[cfg(feature = "std")]
+impl<E, T> CachedObjective<E, T>{
+    pub fn new(inner: E) -> CachedObjective<E, T>{
+        CachedObjective{inner, cache: HashMap::new()}
+    }
+
+    // the number of distinct (x, h) pairs evaluated
+    pub fn len(&self) -> usize{
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.cache.is_empty()
+    }
+
+    // the wrapped objective and every (x, h, value) it was evaluated at, in no particular order
+    pub fn into_parts(self) -> (E, Vec<(T, T, T)>){
+        (self.inner, self.cache.into_values().collect())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, E> Objective<T> for CachedObjective<E, T>
+    where T: Float, E: Objective<T>
+{
+    fn mse(&mut self, x: T, h: T) -> T{
+        let key = (x.integer_decode(), h.integer_decode());
+
+        if let Some(&(_, _, value)) = self.cache.get(&key){
+            return value;
+        }
+
+        let value = self.inner.mse(x, h);
+        self.cache.insert(key, (x, h, value));
+
+        value
+    }
+
+    fn mse_pair(&mut self, x1: T, x2: T, h: T) -> (T, T){
+        let key1 = (x1.integer_decode(), h.integer_decode());
+        let key2 = (x2.integer_decode(), h.integer_decode());
+
+        match (self.cache.get(&key1), self.cache.get(&key2)){
+            (Some(&(_, _, v1)), Some(&(_, _, v2))) => (v1, v2),
+            (None, None)                           => {
+                // neither is known, so keep both evaluations together in case they're concurrent
+                let (v1, v2) = self.inner.mse_pair(x1, x2, h);
+                self.cache.insert(key1, (x1, h, v1));
+                self.cache.insert(key2, (x2, h, v2));
+                (v1, v2)
+            },
+            _                                      => (self.mse(x1, h), self.mse(x2, h)),
+        }
+    }
+
+    fn mse_quad(&mut self, x: [T; 4], h: T) -> [T; 4]{
+        let keys = x.map(|xi| (xi.integer_decode(), h.integer_decode()));
+
+        // as with a pair, keep the evaluations together only if none of them is known
+        if keys.iter().any(|key| self.cache.contains_key(key)){
+            let (v1, v2) = self.mse_pair(x[0], x[1], h);
+            let (v3, v4) = self.mse_pair(x[2], x[3], h);
+            return [v1, v2, v3, v4];
+        }
+
+        let values = self.inner.mse_quad(x, h);
+        for i in 0..4{
+            self.cache.insert(keys[i], (x[i], h, values[i]));
+        }
+
+        values
+    }
+
+    fn grad(&mut self, x: T, h: T) -> Option<T>{
+        self.inner.grad(x, h)
+    }
+
+    fn grad_calls_objective(&self) -> bool{
+        self.inner.grad_calls_objective()
+    }
+
+    fn work(&self) -> Option<usize>{
+        self.inner.work()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, E> Objective<T> for &mut CachedObjective<E, T>
+    where T: Float, E: Objective<T>
+{
+    fn mse(&mut self, x: T, h: T) -> T{
+        (**self).mse(x, h)
+    }
+
+    fn mse_pair(&mut self, x1: T, x2: T, h: T) -> (T, T){
+        (**self).mse_pair(x1, x2, h)
+    }
+
+    fn mse_quad(&mut self, x: [T; 4], h: T) -> [T; 4]{
+        (**self).mse_quad(x, h)
+    }
+
+    fn grad(&mut self, x: T, h: T) -> Option<T>{
+        (**self).grad(x, h)
+    }
+
+    fn grad_calls_objective(&self) -> bool{
+        (**self).grad_calls_objective()
+    }
+
+    fn work(&self) -> Option<usize>{
+        (**self).work()
+    }
+}
+
+// an objective of a complex argument, whose gradient is found by the complex step method. The
+// step is the stencil size h
+pub(crate) struct ComplexStep<F>(pub F);
+
+impl<F> Objective<f64> for ComplexStep<F>
+    where F: FnMut(Complex<f64>, f64) -> Complex<f64>
+{
+    fn mse(&mut self, x: f64, h: f64) -> f64{
+        (self.0)(Complex::new(x, 0.0), h).re
+    }
+
+    fn grad(&mut self, x: f64, h: f64) -> Option<f64>{
+        Some((self.0)(Complex::new(x, h), h).im/h)
+    }
+
+    fn grad_calls_objective(&self) -> bool{
+        true
+    }
+}
+
+// evaluates the points of a pair, or of a four point stencil, concurrently on the rayon thread pool
+#[cfg(feature = "parallel")]
+pub(crate) struct Parallel<F>(pub F);
+
+#[cfg(feature = "parallel")]
+impl<T, F> Objective<T> for Parallel<F>
+    where T: Copy + Send, F: Fn(T, T) -> T + Sync
+{
+    fn mse(&mut self, x: T, h: T) -> T{
+        (self.0)(x, h)
+    }
+
+    fn mse_pair(&mut self, x1: T, x2: T, h: T) -> (T, T){
+        let f = &self.0;
+        rayon::join(move || f(x1, h), move || f(x2, h))
+    }
+
+    fn mse_quad(&mut self, x: [T; 4], h: T) -> [T; 4]{
+        let f = &self.0;
+        let ((v1, v2), (v3, v4)) = rayon::join(move || rayon::join(move || f(x[0], h), move || f(x[1], h)),
+                                               move || rayon::join(move || f(x[2], h), move || f(x[3], h)));
+
+        [v1, v2, v3, v4]
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn quad(x: f64, _h: f64) -> f64{
+        (x - 1.0)*(x - 1.0)
+    }
+
+    #[test]
+    fn mse_quad_agrees_with_mse(){
+        let x        = [0.0, 0.5, 1.5, 3.0];
+        let expected = x.map(|xi| quad(xi, 0.1));
+        let mut f    = quad;
+
+        assert_eq!(f.mse_quad(x, 0.1), expected);
+        assert_eq!(Negated(&mut f).mse_quad(x, 0.1), expected.map(|v| -v));
+        assert_eq!(Averaged{inner: &mut f, samples: 3}.mse_quad(x, 0.1), expected);
+    }
+
+    #[test]
+    fn borrowed_objective_outlives_the_run(){
+        // a problem type with its own state, and an analytic gradient the run should still see
+        struct Problem{
+            calls : usize,
+            grads : usize,
+        }
+
+        impl Objective for Problem{
+            fn mse(&mut self, x: f64, h: f64) -> f64{
+                self.calls += 1;
+                quad(x, h)
+            }
+
+            fn grad(&mut self, x: f64, _h: f64) -> Option<f64>{
+                self.grads += 1;
+                Some(2.0*(x - 1.0))
+            }
+        }
+
+        let mut problem = Problem{calls: 0, grads: 0};
+        let first       = crate::implicit_filtering(problem.by_ref(), 1.5, 0.1, 1e-7).unwrap();
+        let calls       = problem.calls;
+        let second      = crate::implicit_filtering(problem.by_ref(), 1.5, 0.1, 1e-7).unwrap();
+
+        assert_eq!(first, second);
+        assert!(calls > 0 && problem.grads > 0);
+        assert_eq!(problem.calls, 2*calls);
+    }
+
+    #[test]
+    fn counting_stops_mse_quad_at_the_budget(){
+        let mut f        = quad;
+        let mut counting = Counting::new(&mut f, Some(3));
+        let values       = counting.mse_quad([0.0, 0.5, 1.5, 3.0], 0.1);
+
+        assert_eq!(counting.count, 3);
+        assert!(counting.exhausted);
+        assert_eq!(values[..3], [1.0, 0.25, 0.25]);
+        assert!(values[3].is_nan());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn cached_mse_quad_reuses_known_values(){
+        let mut cached = CachedObjective::new(CountingObjective::new(quad));
+
+        cached.mse(0.5, 0.1);
+        cached.mse_quad([0.0, 0.5, 1.5, 3.0], 0.1);
+        cached.mse_quad([0.0, 0.5, 1.5, 3.0], 0.1);
+
+        let (counting, values) = cached.into_parts();
+        assert_eq!(counting.count(), 4);
+        assert_eq!(values.len(), 4);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_mse_quad_agrees_with_mse(){
+        let x = [0.0, 0.5, 1.5, 3.0];
+
+        assert_eq!(Parallel(quad).mse_quad(x, 0.1), x.map(|xi| quad(xi, 0.1)));
+    }
+
+    #[test]
+    fn complex_step_gradient_is_exact_to_rounding(){
+        let mut cubic = ComplexStep(|z: Complex<f64>, _h: f64| z*z*z);
+
+        for &h in &[1e-2, 1e-8, 1e-20, 1e-100, 1e-200]{
+            let grad = cubic.grad(2.0, h).unwrap();
+
+            // Im((x + ih)^3)/h = 3x^2 - h^2, so beyond the largest h the error is only rounding
+            assert!((grad - 12.0).abs() <= 12.0*f64::EPSILON + h*h, "h {}: gradient {}", h, grad);
+        }
+        assert_eq!(cubic.mse(2.0, 1e-8), 8.0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn wrappers_hand_back_their_statistics(){
+        let config     = crate::FilterConfig{verbose: false, ..crate::FilterConfig::default()};
+        let mut cached = CachedObjective::new(CountingObjective::new(quad));
+        let report     = crate::implicit_filtering_report(&mut cached, 1.5, 0.1, 1e-7, &config).unwrap();
+        let size       = cached.len();
+
+        // the cache answers repeated points, so the counter behind it sees each point once
+        let (counting, values) = cached.into_parts();
+        let (mut mse, calls)   = counting.into_parts();
+        assert_eq!(calls, size);
+        assert_eq!(values.len(), size);
+        assert!(calls <= report.eval_count);
+        assert!(values.iter().all(|&(x, h, value)| value == mse.mse(x, h)));
+    }
+}
@@ -0,0 +1,40 @@
+
+// The types and entry points needed for a typical run, e.g.
+//
+//     use implicit_filtering::prelude::*;
+//
+//     let config = FilterConfig{line_search: LineSearch::Wolfe{c1: 1e-4, c2: 0.9}, ..FilterConfig::default()};
+//     let report = implicit_filtering_report(mse, 1.5, 0.1, 1e-7, &config)?;
+//
+// The variants, more specialised entry points and the solvers in euler are left to their own 
+// paths, so that glob importing the prelude doesn't fill a namespace with names like rk2
+
+pub use crate::{implicit_filtering, implicit_filtering_report, implicit_filtering_with, 
+                implicit_filtering_with_callback};
+pub use crate::{ControlFlow, ConvergenceReason, CountingObjective, Fallback, FilterConfig, FilterReport, 
+                GradientScheme, ImplicitFilter, ImplicitFilterIter, IterationRecord, LineSearch, Objective, 
+                OptimDirection, OptimError, OptimResult, StencilKind, StencilSchedule, StepStrategy, ToleranceKind};
+
+#[cfg(feature = "std")]
+pub use crate::{implicit_filtering_nd, implicit_filtering_nd_with, OptimResultND};
+
+#[cfg(test)]
+mod tests{
+    // nothing but the prelude, as a user would import it
+    use crate::prelude::*;
+
+    #[test]
+    fn prelude_is_enough_for_a_run(){
+        let mse    = |x: f64, _h: f64| (x - 1.0).powi(2);
+        let config = FilterConfig{line_search: LineSearch::Wolfe{c1: 1e-4, c2: 0.9}, verbose: false, ..FilterConfig::default()};
+        let report = implicit_filtering_report(mse, 1.5, 0.1, 1e-7, &config).unwrap();
+
+        assert!(report.converged());
+        assert!((report.result.x - 1.0).abs() < 1e-6);
+
+        let mut counting = CountingObjective::new(mse);
+        let result       = implicit_filtering_with(&mut counting, 1.5, 0.1, 1e-7, &config).unwrap();
+        assert_eq!(result, report.result);
+        assert_eq!(counting.count(), report.eval_count);
+    }
+}
@@ -0,0 +1,29 @@
+
+// A small seeded pseudo-random number generator, SplitMix64. The randomised entry points draw 
+// everything from one of these, seeded by the caller, so that a run can be repeated exactly. 
+// Nothing in the crate uses thread-local or system entropy
+
+pub(crate) struct SplitMix64{
+    state: u64,
+}
+
+impl SplitMix64{
+    pub fn new(seed: u64) -> SplitMix64{
+        SplitMix64{state: seed}
+    }
+
+    pub fn next_u64(&mut self) -> u64{
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform on [lo, hi), using the top 53 bits so that every value is exactly representable
+    pub fn uniform(&mut self, lo: f64, hi: f64) -> f64{
+        let u = (self.next_u64() >> 11) as f64/(1u64 << 53) as f64;
+        lo + (hi - lo)*u
+    }
+}
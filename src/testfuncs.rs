@@ -0,0 +1,89 @@
+
+// Objective functions for trying out the optimiser and its settings, shared by the benchmarks and
+// available to users. Each takes (x, h) like any other objective, although only exponential_mse
+// depends on h. The global minimum of each is given alongside it
+
+use std::f64::consts::PI;
+
+use crate::euler::rk2_from;
+
+const BETA          : f64 = 1.0;
+const INITIAL_TIME  : f64 = 0.0;
+const INITIAL_VALUE : f64 = 1.0;
+const FINAL_TIME    : f64 = 5.0;
+
+// the amplitude of the oscillation added to noisy_quadratic, and the resolution of quantized_quadratic
+const NOISE_AMPLITUDE : f64 = 1e-2;
+const QUANTUM         : f64 = 1e-3;
+
+// (x - 1)², with its minimum of 0 at x = 1
+pub fn quadratic(x: f64, _h: f64) -> f64{
+    (x - 1.0).powi(2)
+}
+
+// the Rosenbrock function (1 - x)² + 100(y - x²)² along the line y = 1. The minimum is 0 at x = 1,
+// and there is a second, local, minimum near x = -1
+pub fn rosenbrock_slice(x: f64, _h: f64) -> f64{
+    (1.0 - x).powi(2) + 100.0*(1.0 - x*x).powi(2)
+}
+
+// the Rastrigin function 10 + x² - 10cos(2πx), with its minimum of 0 at x = 0 surrounded by a 
+// local minimum near every integer
+pub fn rastrigin(x: f64, _h: f64) -> f64{
+    10.0 + x*x - 10.0*(2.0*PI*x).cos()
+}
+
+// quadratic with a rapid, small oscillation added, of the kind implicit filtering is designed to
+// step over. The oscillation vanishes at x = 1, so the minimum is still 0 at x = 1, but the 
+// function has a local minimum every 0.02 or so near it
+pub fn noisy_quadratic(x: f64, _h: f64) -> f64{
+    (x - 1.0).powi(2) + NOISE_AMPLITUDE*(1.0 - (100.0*PI*x).cos())
+}
+
+// quadratic rounded to the nearest multiple of QUANTUM, like the output of a simulation that only
+// reports a few significant figures. The minimum of 0 is attained on the whole interval 
+// |x - 1| < sqrt(QUANTUM/2), and the function is flat between the steps
+pub fn quantized_quadratic(x: f64, _h: f64) -> f64{
+    ((x - 1.0).powi(2)/QUANTUM).round()*QUANTUM
+}
+
+// the squared error at the final time of the rk2 solution of y' = xy, with stepsize h, against 
+// the exact solution of y' = βy. The global minimum is near x = β, with the error of the method 
+// at stepsize h moving it slightly away
+pub fn exponential_mse(x: f64, h: f64) -> f64{
+    let true_val      = INITIAL_VALUE*(BETA*(FINAL_TIME - INITIAL_TIME)).exp();
+    let estimated_val = rk2_from(x, h, INITIAL_TIME, INITIAL_VALUE, FINAL_TIME);
+
+    (true_val - estimated_val).powi(2)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{implicit_filtering_with, FilterConfig};
+
+    fn minimiser(mse: fn(f64, f64) -> f64, x0: f64, h0: f64, config: &FilterConfig) -> f64{
+        implicit_filtering_with(mse, x0, h0, 1e-7, config).unwrap().x
+    }
+
+    #[test]
+    fn minimisers_are_recovered(){
+        let config = FilterConfig{verbose: false, ..FilterConfig::default()};
+
+        assert!((minimiser(quadratic, 3.0, 0.1, &config) - 1.0).abs() < 1e-6);
+        assert!((minimiser(rosenbrock_slice, 0.8, 0.1, &config) - 1.0).abs() < 1e-6);
+        // started well inside the basin of the global minimum, since a coarse first stencil can jump
+        // to a neighbouring one
+        assert!(minimiser(rastrigin, 0.2, 0.05, &config).abs() < 1e-6);
+        assert!((minimiser(noisy_quadratic, 2.0, 0.5, &config) - 1.0).abs() < 0.05);
+        assert!((minimiser(quantized_quadratic, 2.0, 0.5, &config) - 1.0).abs() < (QUANTUM/2.0).sqrt());
+    }
+
+    #[test]
+    fn exponential_mse_is_minimised_near_beta(){
+        // the stencil sizes stop at 0.1/64, where the steps of the ODE solver are still cheap
+        let config = FilterConfig{verbose: false, max_outer_iters: 4, ..FilterConfig::default()};
+
+        assert!((minimiser(exponential_mse, 1.5, 0.1, &config) - BETA).abs() < 1e-2);
+    }
+}